@@ -1,13 +1,34 @@
 use std::{
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
 };
 
 use serde::Deserialize;
 
+use crate::DeriveMap;
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct Package {
     name: String,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Metadata {
+    #[serde(default)]
+    traitable: TraitableMetadata,
+}
+
+/// The `[package.metadata.traitable]` table.
+#[derive(Debug, Default, Deserialize)]
+pub struct TraitableMetadata {
+    /// Maps derive macros to the traits they implement, for example:
+    /// `MessageDerive = ["Message"]`
+    #[serde(default)]
+    derives: BTreeMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,6 +51,18 @@ impl Manifest {
         })
     }
 
+    pub fn derives(&self) -> DeriveMap {
+        let mut derives = DeriveMap::default();
+
+        if let Some(package) = &self.data.package {
+            for (derive, traits) in &package.metadata.traitable.derives {
+                derives.insert(derive, traits.iter().cloned());
+            }
+        }
+
+        derives
+    }
+
     pub fn get_entry(&self) -> Result<PathBuf, crate::Error> {
         let src = self.path.to_owned();
         let src = src.parent().unwrap();
@@ -1,4 +1,5 @@
 mod manifest;
 mod resolver;
 
-pub use resolver::{entry_file, entry_file_from_env};
+pub use manifest::Manifest;
+pub use resolver::{entry_file, entry_file_from_env, manifest_from_env};
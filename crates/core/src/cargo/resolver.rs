@@ -1,5 +1,5 @@
 use std::{
-    env, fs,
+    env,
     path::{Path, PathBuf},
 };
 
@@ -16,13 +16,21 @@ pub fn entry_file_from_env() -> Result<PathBuf, crate::Error> {
     )
 }
 
+pub fn manifest_from_env() -> Result<Manifest, crate::Error> {
+    Manifest::from_file(
+        env::var("CARGO_MANIFEST_DIR")
+            .map(PathBuf::from)
+            .unwrap()
+            .join("Cargo.toml"),
+    )
+}
+
 pub fn entry_file(
     manifest_file: impl AsRef<Path>,
-    crate_name: impl AsRef<str>,
-    bin_name: Option<impl AsRef<str>>,
+    _crate_name: impl AsRef<str>,
+    _bin_name: Option<impl AsRef<str>>,
 ) -> Result<PathBuf, crate::Error> {
     let manifest_file = manifest_file.as_ref();
-    let crate_name = crate_name.as_ref();
 
     Manifest::from_file(manifest_file)?.get_entry()
 }
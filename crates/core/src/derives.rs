@@ -0,0 +1,52 @@
+/// Maps derive macros to the traits they implement. Derives without an entry
+/// are assumed to implement the trait with the same name.
+#[derive(Debug, Default, Clone)]
+pub struct DeriveMap {
+    entries: Vec<(Vec<String>, Vec<String>)>,
+}
+
+impl DeriveMap {
+    /// Adds the traits that are implemented by the given derive. The derive may
+    /// be a single name (`MessageDerive`) or a path (`proto::Message`), which
+    /// has to match the end of the resolved path of the derive.
+    pub fn insert(&mut self, derive: &str, traits: impl IntoIterator<Item = String>) {
+        let key = split_path(derive);
+        let traits = traits.into_iter().collect();
+
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = traits,
+            None => self.entries.push((key, traits)),
+        }
+    }
+
+    pub fn extend(&mut self, other: DeriveMap) {
+        for (key, traits) in other.entries {
+            self.insert(&key.join("::"), traits);
+        }
+    }
+
+    /// Returns the names of the traits that are implemented by the derive with
+    /// the given (resolved) path.
+    pub fn traits_of(&self, derive_path: &str) -> Vec<String> {
+        let path = split_path(derive_path);
+
+        let entry = self
+            .entries
+            .iter()
+            .filter(|(key, _)| path.ends_with(key))
+            .max_by_key(|(key, _)| key.len());
+
+        match entry {
+            Some((_, traits)) => traits.clone(),
+            None => path.last().cloned().into_iter().collect(),
+        }
+    }
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    path.split("::")
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
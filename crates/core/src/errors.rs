@@ -10,6 +10,12 @@ pub enum Error {
     #[error("parsing toml failed: {0}")]
     ParseToml(#[from] basic_toml::Error),
 
+    #[error("parsing source failed: {0}")]
+    ParseSource(#[from] syn::Error),
+
     #[error("could not resolve entry file")]
     EntryNotFound,
+
+    #[error("entry file must be `lib.rs` or `main.rs`")]
+    InvalidEntry,
 }
@@ -1,6 +1,11 @@
 pub mod cargo;
+mod derives;
 mod errors;
+mod path;
 mod scanner;
+mod scope;
 
+pub use derives::DeriveMap;
 pub use errors::Error;
-pub use scanner::{parse, Implementer};
+pub use path::{PathFilter, PathPattern};
+pub use scanner::{parse, AstNode, Field, File, Implementer, Kind, Options, Shape, Variant};
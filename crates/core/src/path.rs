@@ -0,0 +1,54 @@
+/// A pattern for the paths of implementers, where `*` matches a single segment
+/// and `**` any number of segments. For example: `crate::api::*` matches
+/// `crate::api::GetSelf` but not `crate::api::users::GetSelf`, which is matched
+/// by `crate::api::**`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathPattern {
+    segments: Vec<String>,
+}
+
+/// Restricts a lookup to the implementers whose paths match one of the included
+/// patterns, if there are any, and none of the excluded ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathFilter {
+    pub include: Vec<PathPattern>,
+    pub exclude: Vec<PathPattern>,
+}
+
+impl PathPattern {
+    /// Creates a pattern from a path like `crate::api::**`. Paths that do not
+    /// start with `crate` are relative to the crate root.
+    pub fn new(pattern: &str) -> Self {
+        let mut segments: Vec<String> = pattern.split("::").map(|s| s.trim().to_string()).collect();
+
+        if segments[0] != "crate" {
+            segments.insert(0, "crate".to_string());
+        }
+
+        PathPattern { segments }
+    }
+
+    /// Returns true if the full path of a type, like `crate::api::GetSelf`,
+    /// matches the pattern.
+    pub fn matches(&self, path: &str) -> bool {
+        fn matches(pattern: &[String], path: &[&str]) -> bool {
+            match (pattern.split_first(), path.split_first()) {
+                (None, None) => true,
+                (Some((p, rest)), _) if p == "**" => {
+                    (0..=path.len()).any(|skip| matches(rest, &path[skip..]))
+                }
+                (Some((p, rest)), Some((s, path))) => (p == "*" || p == s) && matches(rest, path),
+                _ => false,
+            }
+        }
+
+        matches(&self.segments, &path.split("::").collect::<Vec<_>>())
+    }
+}
+
+impl PathFilter {
+    pub fn matches(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(path)))
+            && !self.exclude.iter().any(|p| p.matches(path))
+    }
+}
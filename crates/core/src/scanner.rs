@@ -3,32 +3,196 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use proc_macro2::TokenTree;
-use syn::{token::Impl, Item, ItemImpl, ItemMod, ItemStruct, Meta, Type};
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{
+    meta::ParseNestedMeta, punctuated::Punctuated, visit_mut::VisitMut, Attribute, Fields,
+    ImplItem, Item, ItemEnum, ItemImpl, ItemMod, ItemStruct, Lit, LitInt, LitStr, Meta,
+    PathArguments, Token, Type,
+};
+
+use crate::{
+    scope::{PathResolver, Scope},
+    DeriveMap, PathFilter,
+};
 
 #[derive(Debug)]
 pub struct File {
     items: Vec<AstNode>,
+    derives: DeriveMap,
 }
 
 #[derive(Debug)]
 pub enum AstNode {
-    Module { name: String, items: Vec<AstNode> },
-    Struct { name: String },
-    TraitImpl { trait_name: String, target: String },
-    Derive { trait_name: String, target: String },
+    Module {
+        name: String,
+        items: Vec<AstNode>,
+    },
+    Struct {
+        name: String,
+        attrs: Vec<String>,
+        options: Options,
+        shape: Shape,
+        fields: Vec<Field>,
+    },
+    Enum {
+        name: String,
+        attrs: Vec<String>,
+        options: Options,
+        variants: Vec<Variant>,
+    },
+    TraitImpl {
+        trait_name: String,
+        trait_args: Vec<String>,
+        target: String,
+
+        /// The module of the implementing type, if its path could be resolved.
+        target_module: Option<String>,
+        assoc: Vec<(String, String)>,
+        consts: Vec<(String, String)>,
+        is_unsafe: bool,
+        is_default: bool,
+        cfg: Option<String>,
+        options: Options,
+    },
+    Derive {
+        derive_path: String,
+        target: String,
+        cfg: Option<String>,
+    },
 }
 
 #[derive(Debug)]
 pub struct Context {
     search_path_stack: Vec<PathBuf>,
     module_name_stack: Vec<String>,
+    scope_stack: Vec<Scope>,
+
+    /// The `cfg` predicates of all modules that are currently entered.
+    cfg_stack: Vec<TokenStream>,
+}
+
+/// The options of the `#[traitable(...)]` attributes of a type definition or an
+/// impl block. For example: `#[traitable(rename = "Login", tag = "admin")]`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+    /// Whether the implementer is left out of all queries.
+    pub skip: bool,
+
+    /// The name that `$ty` is replaced with.
+    pub rename: Option<String>,
+
+    pub tags: Vec<String>,
+
+    /// The index that `$index` is set to, instead of the position in the scan
+    /// order.
+    pub index: Option<usize>,
+
+    /// All other keys with their values, which are literals or paths resolved
+    /// from the crate root. For example: `("route", "\"/users/self\"")` for
+    /// `#[traitable(route = "/users/self")]`
+    pub meta: Vec<(String, String)>,
+}
+
+/// Whether an implementer is defined as a struct or as an enum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Struct,
+    Enum,
 }
 
-#[derive(Debug, PartialEq)]
+/// Whether a struct has named fields, tuple fields or no fields at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Named,
+    Tuple,
+    Unit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// The name of the field, or `None` for fields of tuple structs.
+    pub name: Option<String>,
+
+    /// The type of the field, with all paths resolved from the crate root.
+    pub ty: String,
+
+    /// The attributes of the field, like `#[serde(skip)]`.
+    pub attrs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub attrs: Vec<String>,
+    pub shape: Shape,
+    pub fields: Vec<Field>,
+
+    /// The explicit discriminant of the variant, like `0x10` in `Ping = 0x10`.
+    pub discriminant: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Implementer {
     pub name: String,
     pub path: String,
+
+    /// The generic arguments of the implemented trait, with all paths resolved
+    /// from the crate root. For example: `crate :: users :: GetSelf` for
+    /// `impl Handler<GetSelf> for UserService`
+    pub trait_args: Vec<String>,
+
+    /// The associated types and consts that are defined in the impl block.
+    /// Types are given with all paths resolved from the crate root, consts as
+    /// a fully qualified path to the const (`<Type as Trait>::NAME`).
+    pub assoc: Vec<(String, String)>,
+
+    /// The expressions of the associated consts, with all paths resolved from
+    /// the crate root.
+    pub consts: Vec<(String, String)>,
+
+    /// Whether the trait is implemented with `unsafe impl`.
+    pub is_unsafe: bool,
+
+    /// Whether the trait is implemented with `default impl`.
+    pub is_default: bool,
+
+    /// The `cfg` predicate that has to hold for the trait to be implemented,
+    /// combining the `cfg` attributes of the impl (or `cfg_attr` of the derive)
+    /// and its surrounding items. For example: `all(unix, feature = "serde")`
+    pub cfg: Option<String>,
+
+    /// Whether the implementer is a struct or an enum, if its definition was
+    /// found.
+    pub kind: Option<Kind>,
+
+    /// The attributes of the type definition, like `#[derive(Clone)]`.
+    pub attrs: Vec<String>,
+
+    /// The shape of the implementing struct, if its definition was found.
+    pub shape: Option<Shape>,
+
+    /// The fields of the implementing struct.
+    pub fields: Vec<Field>,
+
+    /// The variants of the implementing enum.
+    pub variants: Vec<Variant>,
+
+    /// The name given with `#[traitable(rename = "...")]`, on the impl block
+    /// or the type definition.
+    pub rename: Option<String>,
+
+    /// The tags given with `#[traitable(tag = "...")]`, on the type definition
+    /// and the impl block.
+    pub tags: Vec<String>,
+
+    /// The index given with `#[traitable(index = N)]`, on the impl block or
+    /// the type definition.
+    pub index: Option<usize>,
+
+    /// The other keys and values of `#[traitable(...)]`, where the ones of the
+    /// impl block take precedence over the ones of the type definition.
+    pub meta: Vec<(String, String)>,
 }
 
 impl From<&str> for Implementer {
@@ -39,10 +203,115 @@ impl From<&str> for Implementer {
         Self {
             name: name.to_string(),
             path: segments.join("::"),
+            ..Default::default()
+        }
+    }
+}
+
+impl Options {
+    /// Parses a single option of a `#[traitable(...)]` attribute.
+    pub fn parse_meta(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("skip") {
+            self.skip = true;
+        } else if meta.path.is_ident("rename") {
+            let name = meta.value()?.parse::<LitStr>()?;
+
+            if syn::parse_str::<syn::Ident>(&name.value()).is_err() {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!("`{}` is not a valid identifier", name.value()),
+                ));
+            }
+
+            self.rename = Some(name.value());
+        } else if meta.path.is_ident("tag") {
+            self.tags.push(meta.value()?.parse::<LitStr>()?.value());
+        } else if meta.path.is_ident("index") {
+            self.index = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+        } else if let Some(key) = meta.path.get_ident() {
+            let Ok(input) = meta.value() else {
+                return Err(meta.error(format!("expected a value, like `{key} = \"...\"`")));
+            };
+
+            let value = match input.peek(Lit) {
+                true => input.parse::<Lit>()?.to_token_stream(),
+                false => input.parse::<syn::Path>()?.to_token_stream(),
+            };
+
+            self.set(key.to_string(), value.to_string());
+        } else {
+            return Err(meta.error(format!(
+                "unknown option `{}`",
+                meta.path.to_token_stream().to_string().replace(' ', "")
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Combines the options of a type definition with the ones of an impl
+    /// block, which take precedence.
+    fn merge(mut self, other: &Options) -> Options {
+        self.skip |= other.skip;
+
+        if other.rename.is_some() {
+            self.rename = other.rename.clone();
+        }
+
+        if other.index.is_some() {
+            self.index = other.index;
+        }
+
+        for tag in &other.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+
+        for (key, value) in &other.meta {
+            self.set(key.clone(), value.clone());
+        }
+
+        self
+    }
+
+    fn set(&mut self, key: String, value: String) {
+        match self.meta.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.meta.push((key, value)),
         }
     }
 }
 
+/// Collects the options of all `#[traitable(...)]` attributes. Invalid options
+/// are reported by the attribute macro itself, so they are ignored here.
+fn options(attrs: &[Attribute], scope: &Scope) -> Options {
+    let mut options = Options::default();
+
+    for attr in attrs {
+        let path = path_to_string(attr.path());
+
+        if path == "traitable" || path == "traitable::traitable" {
+            let _ = attr.parse_nested_meta(|meta| options.parse_meta(meta));
+        }
+    }
+
+    let mut resolver = PathResolver {
+        scope,
+        self_ty: None,
+        self_trait: None,
+    };
+
+    for (_, value) in &mut options.meta {
+        if let Ok(mut path) = syn::parse_str::<syn::Path>(value) {
+            resolver.visit_path_mut(&mut path);
+            *value = path.to_token_stream().to_string();
+        }
+    }
+
+    options
+}
+
 impl Context {
     fn push_mod(&mut self, name: &str) {
         self.module_name_stack.push(name.to_string());
@@ -51,7 +320,7 @@ impl Context {
             self.search_path_stack
                 .last()
                 .unwrap()
-                .join(&name)
+                .join(name)
                 .to_path_buf(),
         );
     }
@@ -60,67 +329,161 @@ impl Context {
         self.search_path_stack.pop();
         self.module_name_stack.pop();
     }
+
+    /// Combines the `cfg` predicates of the surrounding modules with the given
+    /// ones into a single predicate.
+    fn cfg(&self, predicates: &[TokenStream]) -> Option<String> {
+        let all: Vec<_> = self.cfg_stack.iter().chain(predicates).collect();
+
+        match all.as_slice() {
+            [] => None,
+            [single] => Some(single.to_string()),
+            _ => Some(quote!(all(#(#all),*)).to_string()),
+        }
+    }
+}
+
+/// Returns the predicates of all `#[cfg(...)]` attributes.
+fn cfg_predicates(attrs: &[Attribute]) -> Vec<TokenStream> {
+    attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::List(l) if l.path.is_ident("cfg") => Some(l.tokens.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collects the paths of all derives in the given attributes, together with the
+/// predicates of the `cfg_attr` attributes they are nested in.
+fn collect_derives(
+    metas: impl IntoIterator<Item = Meta>,
+    predicates: &[TokenStream],
+    derives: &mut Vec<(syn::Path, Vec<TokenStream>)>,
+) {
+    for meta in metas {
+        let Meta::List(l) = meta else {
+            continue;
+        };
+
+        if l.path.is_ident("derive") {
+            let Ok(paths) = l.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
+            else {
+                continue;
+            };
+
+            derives.extend(paths.into_iter().map(|p| (p, predicates.to_vec())));
+        } else if l.path.is_ident("cfg_attr") {
+            let Ok(args) = l.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+            else {
+                continue;
+            };
+
+            let mut args = args.into_iter();
+
+            let Some(predicate) = args.next() else {
+                continue;
+            };
+
+            let mut predicates = predicates.to_vec();
+            predicates.push(predicate.into_token_stream());
+
+            collect_derives(args, &predicates, derives);
+        }
+    }
 }
 
-pub fn parse(entry: impl AsRef<Path>) -> Result<File, ()> {
+pub fn parse(entry: impl AsRef<Path>) -> Result<File, crate::Error> {
     let entry = entry.as_ref();
 
     let Some(filename) = entry.file_name().and_then(OsStr::to_str) else {
-        return Err(());
+        return Err(crate::Error::InvalidEntry);
     };
 
     if !matches!(filename, "lib.rs" | "main.rs") {
-        return Err(());
+        return Err(crate::Error::InvalidEntry);
     }
 
     let mut ctx = Context {
         search_path_stack: vec![entry.parent().unwrap().to_path_buf()],
         module_name_stack: vec![],
+        scope_stack: vec![],
+        cfg_stack: vec![],
     };
 
     Ok(File {
         items: parse_module_file(&mut ctx, entry)?,
+        derives: DeriveMap::default(),
     })
 }
 
-fn parse_module_file(mut ctx: &mut Context, file: impl AsRef<Path>) -> Result<Vec<AstNode>, ()> {
-    let file = file.as_ref();
+fn path_to_string(path: &syn::Path) -> String {
+    path.segments
+        .iter()
+        .map(|s| s.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::")
+}
 
-    let source = std::fs::read_to_string(file).map_err(|_| ())?;
+fn parse_module_file(
+    ctx: &mut Context,
+    file: impl AsRef<Path>,
+) -> Result<Vec<AstNode>, crate::Error> {
+    let source = std::fs::read_to_string(file)?;
 
-    Ok(syn::parse_file(&source).unwrap().items.to_ast(&mut ctx))
+    syn::parse_file(&source)?.items.to_ast(ctx)
 }
 
 trait ToAst {
-    fn to_ast(self, ctx: &mut Context) -> Vec<AstNode>;
+    fn to_ast(self, ctx: &mut Context) -> Result<Vec<AstNode>, crate::Error>;
 }
 
 impl ToAst for Item {
-    fn to_ast(self, mut ctx: &mut Context) -> Vec<AstNode> {
+    fn to_ast(self, ctx: &mut Context) -> Result<Vec<AstNode>, crate::Error> {
         match self {
-            Item::Impl(i) => i.to_ast(&mut ctx),
-            Item::Mod(m) => m.to_ast(&mut ctx),
-            Item::Struct(s) => s.to_ast(&mut ctx),
-            _ => Vec::new(),
+            Item::Impl(i) => i.to_ast(ctx),
+            Item::Mod(m) => m.to_ast(ctx),
+            Item::Struct(s) => s.to_ast(ctx),
+            Item::Enum(e) => e.to_ast(ctx),
+            _ => Ok(Vec::new()),
         }
     }
 }
 
 impl ToAst for Vec<Item> {
-    fn to_ast(self, mut ctx: &mut Context) -> Vec<AstNode> {
-        self.into_iter().flat_map(|i| i.to_ast(&mut ctx)).collect()
+    fn to_ast(self, ctx: &mut Context) -> Result<Vec<AstNode>, crate::Error> {
+        ctx.scope_stack
+            .push(Scope::new(&ctx.module_name_stack, &self));
+
+        let mut ast = Vec::new();
+
+        for item in self {
+            match item.to_ast(ctx) {
+                Ok(nodes) => ast.extend(nodes),
+                Err(e) => {
+                    ctx.scope_stack.pop();
+                    return Err(e);
+                }
+            }
+        }
+
+        ctx.scope_stack.pop();
+
+        Ok(ast)
     }
 }
 
 impl ToAst for ItemMod {
-    fn to_ast(self, mut ctx: &mut Context) -> Vec<AstNode> {
+    fn to_ast(self, ctx: &mut Context) -> Result<Vec<AstNode>, crate::Error> {
         let mod_name = self.ident.to_string();
         let search = ctx.search_path_stack.last().cloned().unwrap();
+        let cfg_len = ctx.cfg_stack.len();
 
         ctx.push_mod(&mod_name);
+        ctx.cfg_stack.extend(cfg_predicates(&self.attrs));
 
         let items = match self.content {
-            Some((_, items)) => items.to_ast(&mut ctx),
+            Some((_, items)) => items.to_ast(ctx),
             None => {
                 let mod_file = [
                     search.join(&mod_name).with_extension("rs"),
@@ -129,92 +492,300 @@ impl ToAst for ItemMod {
                 .into_iter()
                 .find(|p| p.exists());
 
-                if let Some(f) = mod_file {
-                    parse_module_file(ctx, f).unwrap()
-                } else {
-                    Vec::new()
+                match mod_file {
+                    Some(f) => parse_module_file(ctx, f),
+                    None => Ok(Vec::new()),
                 }
             }
         };
 
         ctx.pop_mod();
+        ctx.cfg_stack.truncate(cfg_len);
 
-        vec![AstNode::Module {
+        Ok(vec![AstNode::Module {
             name: self.ident.to_string(),
-            items,
-        }]
+            items: items?,
+        }])
     }
 }
 
+/// Returns the resolved path of a type definition, which `Self` refers to
+/// inside of it.
+fn self_path(scope: &Scope, ident: &syn::Ident) -> syn::Path {
+    syn::Path {
+        leading_colon: None,
+        segments: scope
+            .resolve(&[ident.to_string()])
+            .unwrap_or_default()
+            .iter()
+            .map(|s| syn::PathSegment::from(syn::Ident::new(s, ident.span())))
+            .collect(),
+    }
+}
+
+fn fields_to_ast(fields: Fields, resolver: &mut PathResolver) -> (Shape, Vec<Field>) {
+    let shape = match &fields {
+        Fields::Named(_) => Shape::Named,
+        Fields::Unnamed(_) => Shape::Tuple,
+        Fields::Unit => Shape::Unit,
+    };
+
+    let fields = fields
+        .into_iter()
+        .map(|mut f| {
+            resolver.visit_type_mut(&mut f.ty);
+
+            Field {
+                name: f.ident.map(|i| i.to_string()),
+                ty: f.ty.to_token_stream().to_string(),
+                attrs: attrs_to_strings(&f.attrs),
+            }
+        })
+        .collect();
+
+    (shape, fields)
+}
+
+fn attrs_to_strings(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .map(|a| a.to_token_stream().to_string())
+        .collect()
+}
+
+/// Creates a `Derive` node for each derive in the attributes of a struct or
+/// enum.
+fn derives_to_ast(
+    ctx: &Context,
+    scope: &Scope,
+    target: &syn::Ident,
+    attrs: Vec<Attribute>,
+) -> Vec<AstNode> {
+    let item_cfg = cfg_predicates(&attrs);
+    let mut derives = Vec::new();
+
+    collect_derives(
+        attrs.into_iter().map(|attr| attr.meta),
+        &item_cfg,
+        &mut derives,
+    );
+
+    derives
+        .into_iter()
+        .map(|(mut path, predicates)| {
+            PathResolver {
+                scope,
+                self_ty: None,
+                self_trait: None,
+            }
+            .visit_path_mut(&mut path);
+
+            AstNode::Derive {
+                derive_path: path_to_string(&path),
+                target: target.to_string(),
+                cfg: ctx.cfg(&predicates),
+            }
+        })
+        .collect()
+}
+
 impl ToAst for ItemStruct {
-    fn to_ast(self, _: &mut Context) -> Vec<AstNode> {
+    fn to_ast(self, ctx: &mut Context) -> Result<Vec<AstNode>, crate::Error> {
+        let Some(scope) = ctx.scope_stack.last() else {
+            return Ok(Vec::new());
+        };
+
+        let self_ty = self_path(scope, &self.ident);
+
+        let mut resolver = PathResolver {
+            scope,
+            self_ty: Some(&self_ty),
+            self_trait: None,
+        };
+
+        let (shape, fields) = fields_to_ast(self.fields, &mut resolver);
+
         let mut ast = vec![AstNode::Struct {
             name: self.ident.to_string(),
+            attrs: attrs_to_strings(&self.attrs),
+            options: options(&self.attrs, scope),
+            shape,
+            fields,
         }];
 
-        for attr in self.attrs {
-            if let Meta::List(l) = attr.meta {
-                if let Some(p) = l.path.segments.first() {
-                    if p.ident.to_string() == "derive" {
-                        let mut tokens = l.tokens.into_iter().peekable();
-
-                        loop {
-                            let Some(t) = tokens.next() else {
-                                break;
-                            };
-
-                            match (t, tokens.peek()) {
-                                (TokenTree::Ident(id), Some(TokenTree::Punct(p)))
-                                    if p.as_char() == ',' =>
-                                {
-                                    ast.push(AstNode::Derive {
-                                        trait_name: id.to_string(),
-                                        target: self.ident.to_string(),
-                                    });
-                                }
-                                (TokenTree::Ident(id), None) => {
-                                    ast.push(AstNode::Derive {
-                                        trait_name: id.to_string(),
-                                        target: self.ident.to_string(),
-                                    });
-                                }
-                                _ => (),
-                            }
-                        }
-                    }
+        ast.extend(derives_to_ast(ctx, scope, &self.ident, self.attrs));
+        Ok(ast)
+    }
+}
+
+impl ToAst for ItemEnum {
+    fn to_ast(self, ctx: &mut Context) -> Result<Vec<AstNode>, crate::Error> {
+        let Some(scope) = ctx.scope_stack.last() else {
+            return Ok(Vec::new());
+        };
+
+        let self_ty = self_path(scope, &self.ident);
+
+        let mut resolver = PathResolver {
+            scope,
+            self_ty: Some(&self_ty),
+            self_trait: None,
+        };
+
+        let variants = self
+            .variants
+            .into_iter()
+            .map(|v| {
+                let (shape, fields) = fields_to_ast(v.fields, &mut resolver);
+
+                Variant {
+                    name: v.ident.to_string(),
+                    attrs: attrs_to_strings(&v.attrs),
+                    shape,
+                    fields,
+                    discriminant: v.discriminant.map(|(_, mut expr)| {
+                        resolver.visit_expr_mut(&mut expr);
+                        expr.to_token_stream().to_string()
+                    }),
                 }
-            }
-        }
+            })
+            .collect();
 
-        ast
+        let mut ast = vec![AstNode::Enum {
+            name: self.ident.to_string(),
+            attrs: attrs_to_strings(&self.attrs),
+            options: options(&self.attrs, scope),
+            variants,
+        }];
+
+        ast.extend(derives_to_ast(ctx, scope, &self.ident, self.attrs));
+        Ok(ast)
     }
 }
 
 impl ToAst for ItemImpl {
-    fn to_ast(self, _: &mut Context) -> Vec<AstNode> {
+    fn to_ast(self, ctx: &mut Context) -> Result<Vec<AstNode>, crate::Error> {
+        let cfg = ctx.cfg(&cfg_predicates(&self.attrs));
+
         let ItemImpl {
-            trait_: Some((_, tr, _)),
-            self_ty: ty,
+            attrs,
+            defaultness,
+            unsafety,
+            trait_: Some((None, mut tr, _)),
+            mut self_ty,
+            items,
             ..
         } = self
         else {
-            return Vec::new();
+            // Inherent impls and negative impls (`impl !Trait for Type`) do
+            // not make the type an implementer.
+            return Ok(Vec::new());
         };
 
-        let Type::Path(p) = ty.as_ref() else {
-            return Vec::new();
+        let Some(scope) = ctx.scope_stack.last() else {
+            return Ok(Vec::new());
         };
 
-        vec![AstNode::TraitImpl {
-            trait_name: tr.segments.last().unwrap().ident.to_string(),
+        let options = options(&attrs, scope);
+
+        let mut resolver = PathResolver {
+            scope,
+            self_ty: None,
+            self_trait: None,
+        };
+
+        resolver.visit_path_mut(&mut tr);
+        resolver.visit_type_mut(&mut self_ty);
+
+        let Type::Path(p) = self_ty.as_ref() else {
+            return Ok(Vec::new());
+        };
+
+        let resolver = &mut PathResolver {
+            scope,
+            self_ty: Some(&p.path),
+            self_trait: Some(&tr),
+        };
+
+        let mut assoc = Vec::new();
+        let mut consts = Vec::new();
+
+        for item in items {
+            match item {
+                ImplItem::Type(mut t) => {
+                    resolver.visit_type_mut(&mut t.ty);
+                    assoc.push((t.ident.to_string(), t.ty.to_token_stream().to_string()));
+                }
+                ImplItem::Const(mut c) => {
+                    let name = &c.ident;
+                    assoc.push((name.to_string(), quote!(<#p as #tr>::#name).to_string()));
+
+                    resolver.visit_expr_mut(&mut c.expr);
+                    consts.push((name.to_string(), c.expr.to_token_stream().to_string()));
+                }
+                _ => (),
+            }
+        }
+
+        let target_module = match p.path.segments.first() {
+            Some(first) if first.ident == "crate" => Some(
+                p.path
+                    .segments
+                    .iter()
+                    .take(p.path.segments.len() - 1)
+                    .map(|s| s.ident.to_string())
+                    .collect::<Vec<_>>()
+                    .join("::"),
+            ),
+            _ => None,
+        };
+
+        let trait_segment = tr.segments.last().unwrap();
+
+        let trait_args = match &trait_segment.arguments {
+            PathArguments::AngleBracketed(args) => args
+                .args
+                .iter()
+                .map(|arg| arg.to_token_stream().to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(vec![AstNode::TraitImpl {
+            trait_name: trait_segment.ident.to_string(),
+            trait_args,
             target: p.path.segments.last().unwrap().ident.to_string(),
-        }]
+            target_module,
+            assoc,
+            consts,
+            is_unsafe: unsafety.is_some(),
+            is_default: defaultness.is_some(),
+            cfg,
+            options,
+        }])
     }
 }
 
 impl File {
+    /// Sets the traits that custom derives implement.
+    pub fn with_derives(mut self, derives: DeriveMap) -> Self {
+        self.derives = derives;
+        self
+    }
+
     pub fn lookup(&self, trait_subject: &str) -> Vec<Implementer> {
-        fn find_impl(items: &[AstNode], path: &str, trait_subject: &str) -> Vec<Implementer> {
+        self.lookup_in(trait_subject, &PathFilter::default())
+    }
+
+    /// Looks up the implementers of a trait whose paths match the filter, like
+    /// the ones in `crate::api::**`.
+    pub fn lookup_in(&self, trait_subject: &str, filter: &PathFilter) -> Vec<Implementer> {
+        fn find_impl(
+            items: &[AstNode],
+            path: &str,
+            trait_subject: &str,
+            derives: &DeriveMap,
+        ) -> Vec<(Implementer, Options)> {
             let mut result = Vec::new();
 
             for i in items {
@@ -223,22 +794,53 @@ impl File {
                         items,
                         &(path.to_string() + "::" + name),
                         trait_subject,
+                        derives,
                     )),
-                    AstNode::TraitImpl { trait_name, target } => {
-                        if trait_name == trait_subject {
-                            result.push(Implementer {
-                                path: path.to_string(),
+                    AstNode::TraitImpl {
+                        trait_name,
+                        trait_args,
+                        target,
+                        target_module,
+                        assoc,
+                        consts,
+                        is_unsafe,
+                        is_default,
+                        cfg,
+                        options,
+                    } if trait_name == trait_subject => {
+                        result.push((
+                            Implementer {
+                                path: target_module.as_deref().unwrap_or(path).to_string(),
                                 name: target.to_string(),
-                            });
-                        }
+                                trait_args: trait_args.clone(),
+                                assoc: assoc.clone(),
+                                consts: consts.clone(),
+                                is_unsafe: *is_unsafe,
+                                is_default: *is_default,
+                                cfg: cfg.clone(),
+                                ..Default::default()
+                            },
+                            options.clone(),
+                        ));
                     }
-                    AstNode::Derive { trait_name, target } => {
-                        if trait_name == trait_subject {
-                            result.push(Implementer {
+                    AstNode::Derive {
+                        derive_path,
+                        target,
+                        cfg,
+                    } if derives
+                        .traits_of(derive_path)
+                        .iter()
+                        .any(|t| t == trait_subject) =>
+                    {
+                        result.push((
+                            Implementer {
                                 path: path.to_string(),
                                 name: target.to_string(),
-                            });
-                        }
+                                cfg: cfg.clone(),
+                                ..Default::default()
+                            },
+                            Options::default(),
+                        ));
                     }
                     _ => (),
                 }
@@ -247,7 +849,79 @@ impl File {
             result
         }
 
-        find_impl(&self.items, "crate", trait_subject)
+        let mut result = Vec::new();
+
+        for (mut imp, impl_options) in find_impl(&self.items, "crate", trait_subject, &self.derives)
+        {
+            if !filter.matches(&format!("{}::{}", imp.path, imp.name)) {
+                continue;
+            }
+
+            let options = match self.find(&imp.path, &imp.name) {
+                Some(AstNode::Struct {
+                    attrs,
+                    options,
+                    shape,
+                    fields,
+                    ..
+                }) => {
+                    imp.kind = Some(Kind::Struct);
+                    imp.attrs = attrs.clone();
+                    imp.shape = Some(*shape);
+                    imp.fields = fields.clone();
+                    options.clone()
+                }
+                Some(AstNode::Enum {
+                    attrs,
+                    options,
+                    variants,
+                    ..
+                }) => {
+                    imp.kind = Some(Kind::Enum);
+                    imp.attrs = attrs.clone();
+                    imp.variants = variants.clone();
+                    options.clone()
+                }
+                _ => Options::default(),
+            }
+            .merge(&impl_options);
+
+            if options.skip {
+                continue;
+            }
+
+            imp.rename = options.rename;
+            imp.tags = options.tags;
+            imp.index = options.index;
+            imp.meta = options.meta;
+            result.push(imp);
+        }
+
+        result
+    }
+
+    /// Finds the definition of the item with the given name in the module with
+    /// the given path (like `crate::users`).
+    pub fn find(&self, module: &str, name: &str) -> Option<&AstNode> {
+        let mut segments = module.split("::");
+
+        if segments.next() != Some("crate") {
+            return None;
+        }
+
+        let mut items = &self.items;
+
+        for segment in segments {
+            items = items.iter().find_map(|i| match i {
+                AstNode::Module { name, items } if name == segment => Some(items),
+                _ => None,
+            })?;
+        }
+
+        items.iter().find(|i| match i {
+            AstNode::Struct { name: n, .. } | AstNode::Enum { name: n, .. } => n == name,
+            _ => false,
+        })
     }
 }
 
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use proc_macro2::Span;
+use syn::{
+    punctuated::Punctuated,
+    visit_mut::{self, VisitMut},
+    ExprPath, Ident, Item, Path, PathArguments, PathSegment, QSelf, Type, TypePath, UseTree,
+};
+
+/// The names that are visible inside of a single module, mapped to their
+/// absolute paths (starting with `crate`, or with the name of an external
+/// crate).
+#[derive(Debug)]
+pub struct Scope {
+    module: Vec<String>,
+    names: HashMap<String, Vec<String>>,
+}
+
+impl Scope {
+    pub fn new(module: &[String], items: &[Item]) -> Self {
+        let mut scope = Scope {
+            module: ["crate".to_string()]
+                .into_iter()
+                .chain(module.iter().cloned())
+                .collect(),
+            names: HashMap::new(),
+        };
+
+        // Local items have to be known first, because `use` declarations may
+        // start with the name of a child module.
+        for item in items {
+            let ident = match item {
+                Item::Const(i) => &i.ident,
+                Item::Enum(i) => &i.ident,
+                Item::Fn(i) => &i.sig.ident,
+                Item::Mod(i) => &i.ident,
+                Item::Static(i) => &i.ident,
+                Item::Struct(i) => &i.ident,
+                Item::Trait(i) => &i.ident,
+                Item::TraitAlias(i) => &i.ident,
+                Item::Type(i) => &i.ident,
+                Item::Union(i) => &i.ident,
+                _ => continue,
+            };
+
+            scope
+                .names
+                .insert(ident.to_string(), scope.child(&ident.to_string()));
+        }
+
+        for item in items {
+            if let Item::Use(u) = item {
+                if u.leading_colon.is_none() {
+                    scope.add_use(Vec::new(), &u.tree);
+                }
+            }
+        }
+
+        scope
+    }
+
+    fn child(&self, name: &str) -> Vec<String> {
+        let mut path = self.module.clone();
+        path.push(name.to_string());
+        path
+    }
+
+    fn add_use(&mut self, mut prefix: Vec<String>, tree: &UseTree) {
+        match tree {
+            UseTree::Path(p) => {
+                prefix.push(p.ident.to_string());
+                self.add_use(prefix, &p.tree);
+            }
+            UseTree::Name(n) if n.ident == "self" => {
+                if let Some(last) = prefix.last().cloned() {
+                    self.add_import(last, prefix);
+                }
+            }
+            UseTree::Name(n) => {
+                prefix.push(n.ident.to_string());
+                self.add_import(n.ident.to_string(), prefix);
+            }
+            UseTree::Rename(r) => {
+                if r.ident != "self" {
+                    prefix.push(r.ident.to_string());
+                }
+                self.add_import(r.rename.to_string(), prefix);
+            }
+            UseTree::Group(g) => {
+                for tree in &g.items {
+                    self.add_use(prefix.clone(), tree);
+                }
+            }
+            // The names brought in by glob imports are unknown to us.
+            UseTree::Glob(_) => (),
+        }
+    }
+
+    fn add_import(&mut self, name: String, path: Vec<String>) {
+        if name == "_" {
+            return;
+        }
+
+        let path = self.resolve(&path).unwrap_or(path);
+        self.names.insert(name, path);
+    }
+
+    /// Turns the given path into an absolute one. Returns `None` if the path is
+    /// already absolute, or if it does not refer to anything known in this
+    /// module (like a generic parameter or an item from the prelude).
+    pub fn resolve(&self, path: &[String]) -> Option<Vec<String>> {
+        let (first, rest) = path.split_first()?;
+
+        let mut resolved = match first.as_str() {
+            "self" => self.module.clone(),
+            "super" => {
+                let mut module = self.module.clone();
+                let mut rest = rest;
+
+                module.pop();
+
+                while let Some(("super", tail)) = rest.split_first().map(|(s, t)| (s.as_str(), t)) {
+                    module.pop();
+                    rest = tail;
+                }
+
+                module.extend(rest.iter().cloned());
+                return Some(module);
+            }
+            _ => self.names.get(first)?.clone(),
+        };
+
+        resolved.extend(rest.iter().cloned());
+
+        Some(resolved)
+    }
+}
+
+/// Rewrites all paths inside of a syntax tree, so that they are valid from the
+/// crate root.
+pub struct PathResolver<'a> {
+    pub scope: &'a Scope,
+
+    /// The type that `Self` refers to.
+    pub self_ty: Option<&'a Path>,
+
+    /// The trait of the impl block, whose associated types are referred to
+    /// with `Self::Item`.
+    pub self_trait: Option<&'a Path>,
+}
+
+impl PathResolver<'_> {
+    /// Resolves the leading segments of the path in place. Returns the new
+    /// position of the segment that was at `position` before.
+    fn resolve(&self, path: &mut Path, position: usize) -> usize {
+        if path.leading_colon.is_some() {
+            return position;
+        }
+
+        let Some(first) = path.segments.first() else {
+            return position;
+        };
+
+        // `Self` and items in scope are a single segment, while `super` may be
+        // repeated.
+        let taken = if first.ident == "super" {
+            path.segments
+                .iter()
+                .take_while(|s| s.ident == "super")
+                .count()
+        } else {
+            1
+        };
+
+        let resolved = match self.self_ty {
+            Some(self_ty) if first.ident == "Self" => self_ty.clone(),
+            _ => {
+                let prefix: Vec<_> = path
+                    .segments
+                    .iter()
+                    .take(taken)
+                    .map(|s| s.ident.to_string())
+                    .collect();
+
+                let Some(resolved) = self.scope.resolve(&prefix) else {
+                    return position;
+                };
+
+                Path {
+                    leading_colon: None,
+                    segments: resolved
+                        .iter()
+                        .map(|s| PathSegment::from(Ident::new(s, Span::call_site())))
+                        .collect(),
+                }
+            }
+        };
+
+        let mut segments: Punctuated<PathSegment, _> = resolved.segments;
+
+        // Generic arguments of a replaced segment (like `Wrapper<T>`) have to
+        // be kept.
+        if let (Some(last), Some(replaced)) =
+            (segments.last_mut(), path.segments.iter().nth(taken - 1))
+        {
+            if !matches!(replaced.arguments, PathArguments::None) {
+                last.arguments = replaced.arguments.clone();
+            }
+        }
+
+        let added = segments.len();
+        segments.extend(path.segments.iter().skip(taken).cloned());
+
+        path.leading_colon = resolved.leading_colon;
+        path.segments = segments;
+
+        (position + added).saturating_sub(taken)
+    }
+
+    /// Rewrites `Self::Item` inside of a trait impl to `<Type as Trait>::Item`,
+    /// because `Type::Item` is ambiguous. Returns the position of the item in
+    /// the new path, or `None` for other paths.
+    fn qualify_self(&self, ty: &mut TypePath) -> Option<usize> {
+        let (Some(self_ty), Some(self_trait)) = (self.self_ty, self.self_trait) else {
+            return None;
+        };
+
+        match ty.path.segments.first() {
+            Some(first) if first.ident == "Self" && ty.path.segments.len() > 1 => (),
+            _ => return None,
+        }
+
+        let mut path = self_trait.clone();
+        path.segments
+            .extend(ty.path.segments.iter().skip(1).cloned());
+
+        ty.qself = Some(QSelf {
+            lt_token: Default::default(),
+            ty: Box::new(Type::Path(TypePath {
+                qself: None,
+                path: self_ty.clone(),
+            })),
+            position: self_trait.segments.len(),
+            as_token: Some(Default::default()),
+            gt_token: Default::default(),
+        });
+        ty.path = path;
+
+        Some(self_trait.segments.len())
+    }
+}
+
+impl VisitMut for PathResolver<'_> {
+    fn visit_path_mut(&mut self, path: &mut Path) {
+        self.resolve(path, 0);
+        visit_mut::visit_path_mut(self, path);
+    }
+
+    fn visit_type_path_mut(&mut self, ty: &mut TypePath) {
+        match &mut ty.qself {
+            // In `<T>::Assoc`, the path is relative to `T`.
+            Some(qself) if qself.position == 0 => {
+                self.visit_qself_mut(qself);
+                visit_mut::visit_path_mut(self, &mut ty.path);
+            }
+            Some(qself) => {
+                self.visit_qself_mut(qself);
+                qself.position = self.resolve(&mut ty.path, qself.position);
+                visit_mut::visit_path_mut(self, &mut ty.path);
+            }
+            None => match self.qualify_self(ty) {
+                // The type and the trait are resolved already, only the
+                // arguments of the associated item are left.
+                Some(position) => {
+                    for segment in ty.path.segments.iter_mut().skip(position) {
+                        self.visit_path_segment_mut(segment);
+                    }
+                }
+                None => self.visit_path_mut(&mut ty.path),
+            },
+        }
+    }
+
+    fn visit_expr_path_mut(&mut self, expr: &mut ExprPath) {
+        match &mut expr.qself {
+            Some(qself) if qself.position == 0 => {
+                self.visit_qself_mut(qself);
+                visit_mut::visit_path_mut(self, &mut expr.path);
+            }
+            Some(qself) => {
+                self.visit_qself_mut(qself);
+                qself.position = self.resolve(&mut expr.path, qself.position);
+                visit_mut::visit_path_mut(self, &mut expr.path);
+            }
+            None => self.visit_path_mut(&mut expr.path),
+        }
+    }
+}
@@ -10,6 +10,17 @@ fn test_basic() {
     );
 }
 
+#[test]
+fn test_derives() {
+    let derives = cargo::Manifest::from_file(stub_manifest("messages"))
+        .unwrap()
+        .derives();
+
+    assert_eq!(derives.traits_of("MessageDerive"), ["Message"]);
+    assert_eq!(derives.traits_of("crate::Model"), ["Entity", "Persist"]);
+    assert_eq!(derives.traits_of("serde::Serialize"), ["Serialize"]);
+}
+
 fn stub_dir() -> PathBuf {
     PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("tests/stubs")
 }
@@ -0,0 +1,391 @@
+use std::{env, path::PathBuf};
+
+use syn::parse::Parser;
+use traitable_core::{
+    parse, DeriveMap, Error, Field, Implementer, Kind, Options, PathFilter, PathPattern, Shape,
+    Variant,
+};
+
+#[test]
+fn test_assoc() {
+    let file = parse(stub_entry("messages")).unwrap();
+    let implementers = file.lookup("Message");
+
+    assert_eq!(
+        implementers
+            .iter()
+            .map(|i| format!("{}::{}", i.path, i.name))
+            .collect::<Vec<_>>(),
+        [
+            "crate::users::GetUsers",
+            "crate::users::GetSelf",
+            "crate::Ping"
+        ],
+    );
+
+    assert_eq!(
+        implementers[0].assoc,
+        [
+            (
+                "Response".into(),
+                "std :: collections :: HashMap < usize , Vec < crate :: users :: User > >".into()
+            ),
+            (
+                "NAME".into(),
+                "< crate :: users :: GetUsers as crate :: Message > :: NAME".into()
+            ),
+            (
+                "OPCODE".into(),
+                "< crate :: users :: GetUsers as crate :: Message > :: OPCODE".into()
+            ),
+        ]
+    );
+
+    assert_eq!(
+        implementers[1].assoc[0],
+        (
+            "Response".into(),
+            "Result < crate :: users :: User , crate :: Ping >".into()
+        )
+    );
+
+    assert_eq!(
+        implementers[2].assoc[0],
+        ("Response".into(), "crate :: Ping".into())
+    );
+
+    // `Ping::Item` would be ambiguous, so the trait is given explicitly.
+    assert_eq!(
+        file.lookup("Stream")[0].assoc[1],
+        (
+            "Batch".into(),
+            "Vec < Option < < crate :: Ping as crate :: Stream > :: Item > >".into()
+        )
+    );
+}
+
+#[test]
+fn test_consts() {
+    let file = parse(stub_entry("messages")).unwrap();
+    let implementers = file.lookup("Message");
+
+    assert_eq!(
+        implementers[1].consts,
+        [
+            ("NAME".into(), "\"get_self\"".into()),
+            ("OPCODE".into(), "crate :: users :: BASE + 1".into()),
+        ]
+    );
+
+    assert_eq!(
+        implementers[2].consts,
+        [
+            ("NAME".into(), "\"ping\"".into()),
+            ("OPCODE".into(), "0".into()),
+        ]
+    );
+}
+
+#[test]
+fn test_impl_qualifiers() {
+    let file = parse(stub_entry("messages")).unwrap();
+    let implementers = file.lookup("Wire");
+
+    assert_eq!(
+        implementers
+            .iter()
+            .map(|i| (i.name.as_str(), i.is_unsafe, i.is_default))
+            .collect::<Vec<_>>(),
+        [("Ping", true, false), ("User", true, true)],
+    );
+}
+
+#[test]
+fn test_trait_args() {
+    let file = parse(stub_entry("messages")).unwrap();
+
+    assert_eq!(
+        file.lookup("Handler")
+            .into_iter()
+            .map(|i| (i.name, i.trait_args))
+            .collect::<Vec<_>>(),
+        [
+            ("Service".into(), vec!["crate :: users :: GetSelf".into()]),
+            ("Service".into(), vec!["crate :: Ping".into()]),
+        ]
+    );
+}
+
+#[test]
+fn test_options() {
+    let file = parse(stub_entry("messages")).unwrap();
+
+    assert_eq!(
+        file.lookup("Command")
+            .into_iter()
+            .map(|i| (i.name, i.rename, i.tags, i.index))
+            .collect::<Vec<_>>(),
+        [
+            (
+                "Ban".into(),
+                Some("Kick".into()),
+                vec!["admin".into(), "moderation".into()],
+                Some(7)
+            ),
+            ("Login".into(), None, vec![], None),
+        ]
+    );
+}
+
+#[test]
+fn test_meta() {
+    let file = parse(stub_entry("messages")).unwrap();
+
+    assert_eq!(
+        file.lookup("Route")
+            .into_iter()
+            .map(|i| (i.name, i.meta))
+            .collect::<Vec<_>>(),
+        [
+            (
+                "GetSelf".into(),
+                vec![
+                    ("route".into(), "\"/users/self\"".into()),
+                    ("handler".into(), "crate :: get_self".into()),
+                ]
+            ),
+            (
+                "Pong".into(),
+                vec![
+                    ("method".into(), "\"POST\"".into()),
+                    ("route".into(), "\"/pong\"".into()),
+                    ("retries".into(), "3".into()),
+                ]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_invalid_options() {
+    let parse = |args: &str| {
+        let mut options = Options::default();
+        syn::meta::parser(|meta| options.parse_meta(meta))
+            .parse_str(args)
+            .map(|()| options)
+    };
+
+    assert_eq!(
+        parse("rename = \"GetSelf\"").unwrap().rename.as_deref(),
+        Some("GetSelf")
+    );
+    assert_eq!(
+        parse("rename = \"get-self\"").unwrap_err().to_string(),
+        "`get-self` is not a valid identifier",
+    );
+    assert_eq!(
+        parse("rename = \"type\"").unwrap_err().to_string(),
+        "`type` is not a valid identifier",
+    );
+}
+
+#[test]
+fn test_path_filter() {
+    let file = parse(stub_entry("messages")).unwrap();
+
+    let lookup = |include: &[&str], exclude: &[&str]| {
+        let filter = PathFilter {
+            include: include.iter().map(|p| PathPattern::new(p)).collect(),
+            exclude: exclude.iter().map(|p| PathPattern::new(p)).collect(),
+        };
+
+        file.lookup_in("Message", &filter)
+            .iter()
+            .map(|i| format!("{}::{}", i.path, i.name))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(lookup(&["crate::*"], &[]), ["crate::Ping"]);
+    assert_eq!(
+        lookup(&["crate::users::*"], &[]),
+        ["crate::users::GetUsers", "crate::users::GetSelf"],
+    );
+    assert_eq!(
+        lookup(&["users::GetSelf", "Ping"], &[]),
+        ["crate::users::GetSelf", "crate::Ping"]
+    );
+    assert_eq!(
+        lookup(&["crate::**"], &["crate::users::**"]),
+        ["crate::Ping"],
+    );
+    assert_eq!(
+        lookup(&[], &["crate::*::GetUsers"]),
+        ["crate::users::GetSelf", "crate::Ping"],
+    );
+}
+
+#[test]
+fn test_derives() {
+    let file = parse(stub_entry("messages")).unwrap();
+
+    let names = |implementers: Vec<Implementer>| -> Vec<String> {
+        implementers.into_iter().map(|i| i.name).collect()
+    };
+
+    assert_eq!(names(file.lookup("Serialize")), ["Session"]);
+    assert_eq!(names(file.lookup("MessageDerive")), ["Login"]);
+    assert_eq!(names(file.lookup("Frame")), ["Logout"]);
+    assert!(file.lookup("Entity").is_empty());
+
+    let mut derives = DeriveMap::default();
+    derives.insert("MessageDerive", ["Message".to_string()]);
+    derives.insert("Model", ["Entity".to_string(), "Persist".to_string()]);
+    derives.insert("wire_proto::Frame", ["Message".to_string()]);
+
+    let file = file.with_derives(derives);
+
+    assert_eq!(
+        names(file.lookup("Message")),
+        ["GetUsers", "GetSelf", "Ping", "Login", "Logout"]
+    );
+    assert_eq!(names(file.lookup("Persist")), ["Logout"]);
+    assert!(file.lookup("MessageDerive").is_empty());
+}
+
+#[test]
+fn test_cfg() {
+    let file = parse(stub_entry("cfg")).unwrap();
+
+    let cfgs = |implementers: Vec<Implementer>| -> Vec<(String, Option<String>)> {
+        implementers.into_iter().map(|i| (i.name, i.cfg)).collect()
+    };
+
+    assert_eq!(cfgs(file.lookup("Clone")), [("Settings".into(), None)]);
+
+    assert_eq!(
+        cfgs(file.lookup("Serialize")),
+        [
+            ("Settings".into(), Some("feature = \"serde\"".into())),
+            (
+                "Profile".into(),
+                Some("all (not (test) , unix , feature = \"serde\")".into())
+            ),
+        ]
+    );
+
+    assert_eq!(
+        cfgs(file.lookup("Message")),
+        [
+            (
+                "Frame".into(),
+                Some("all (feature = \"wire\" , unix)".into())
+            ),
+            ("Settings".into(), Some("test".into())),
+        ]
+    );
+}
+
+#[test]
+fn test_fields() {
+    let file = parse(stub_entry("messages")).unwrap();
+    let implementers = file.lookup("Message");
+
+    assert_eq!(implementers[0].shape, Some(Shape::Named));
+    assert_eq!(
+        implementers[0].fields,
+        [
+            Field {
+                name: Some("limit".into()),
+                ty: "usize".into(),
+                attrs: vec![],
+            },
+            Field {
+                name: Some("after".into()),
+                ty: "Option < crate :: users :: User >".into(),
+                attrs: vec!["# [serde (default)]".into()],
+            },
+        ]
+    );
+
+    assert_eq!(implementers[1].shape, Some(Shape::Tuple));
+    assert_eq!(
+        implementers[1].fields,
+        [Field {
+            name: None,
+            ty: "Vec < crate :: users :: GetSelf >".into(),
+            attrs: vec![],
+        }]
+    );
+
+    assert_eq!(implementers[2].shape, Some(Shape::Unit));
+    assert!(implementers[2].fields.is_empty());
+
+    let implementers = file.lookup("Wire");
+
+    assert_eq!(implementers[1].path, "crate::users");
+    assert_eq!(implementers[1].shape, Some(Shape::Unit));
+}
+
+#[test]
+fn test_variants() {
+    let file = parse(stub_entry("messages")).unwrap();
+    let implementers = file.lookup("Clone");
+
+    assert_eq!(implementers[0].name, "Status");
+    assert_eq!(implementers[0].kind, Some(Kind::Enum));
+    assert_eq!(implementers[0].attrs, ["# [derive (Clone)]"]);
+    assert_eq!(implementers[0].shape, None);
+    assert_eq!(
+        implementers[0].variants,
+        [
+            Variant {
+                name: "Online".into(),
+                attrs: vec![],
+                shape: Shape::Unit,
+                fields: vec![],
+                discriminant: Some("crate :: users :: BASE".into()),
+            },
+            Variant {
+                name: "Away".into(),
+                attrs: vec![],
+                shape: Shape::Tuple,
+                fields: vec![Field {
+                    name: None,
+                    ty: "std :: time :: Duration".into(),
+                    attrs: vec![],
+                }],
+                discriminant: None,
+            },
+            Variant {
+                name: "Offline".into(),
+                attrs: vec!["# [doc = \" Not connected since the given status.\"]".into()],
+                shape: Shape::Named,
+                fields: vec![Field {
+                    name: Some("last_seen".into()),
+                    ty: "Option < Box < crate :: users :: Status > >".into(),
+                    attrs: vec![],
+                }],
+                discriminant: None,
+            },
+        ]
+    );
+
+    assert_eq!(implementers[1].name, "Session");
+    assert_eq!(implementers[1].kind, Some(Kind::Struct));
+    assert!(implementers[1].variants.is_empty());
+}
+
+#[test]
+fn test_module_errors() {
+    assert!(matches!(
+        parse(stub_entry("broken")),
+        Err(Error::ParseSource(_))
+    ));
+}
+
+fn stub_entry(stub_name: &str) -> PathBuf {
+    PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("tests/stubs")
+        .join(stub_name)
+        .join("src/lib.rs")
+}
@@ -0,0 +1,3 @@
+mod bad;
+
+pub struct Ok;
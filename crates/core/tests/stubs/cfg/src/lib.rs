@@ -0,0 +1,15 @@
+#[cfg(feature = "wire")]
+mod wire;
+
+pub trait Message {}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings;
+
+#[cfg_attr(unix, cfg_attr(feature = "serde", derive(serde::Serialize)))]
+#[cfg(not(test))]
+pub struct Profile;
+
+#[cfg(test)]
+impl Message for Settings {}
@@ -0,0 +1,6 @@
+use crate::Message;
+
+pub struct Frame;
+
+#[cfg(unix)]
+impl Message for Frame {}
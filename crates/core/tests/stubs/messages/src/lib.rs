@@ -0,0 +1,83 @@
+mod users;
+
+pub trait Message {
+    type Response;
+    const NAME: &'static str;
+    const OPCODE: u16;
+}
+
+pub struct Ping;
+
+impl Message for Ping {
+    type Response = Self;
+    const NAME: &'static str = "ping";
+    const OPCODE: u16 = 0;
+}
+
+pub trait Stream {
+    type Item;
+    type Batch;
+}
+
+impl Stream for Ping {
+    type Item = u8;
+    type Batch = Vec<Option<Self::Item>>;
+}
+
+pub unsafe trait Wire {}
+
+unsafe impl Wire for Ping {}
+
+default unsafe impl Wire for users::User {}
+
+impl !Wire for users::GetSelf {}
+
+use wire_proto as proto;
+
+#[derive(Debug, MessageDerive)]
+pub struct Login;
+
+#[derive(proto::Frame, Model)]
+pub struct Logout;
+
+#[derive(Clone, serde::Serialize)]
+pub struct Session;
+
+pub trait Handler<M: Message> {}
+
+pub struct Service;
+
+impl Handler<users::GetSelf> for Service {}
+
+impl Handler<Ping> for Service {}
+
+pub trait Command {}
+
+#[traitable(tag = "admin")]
+pub struct Ban;
+
+#[traitable::traitable(rename = "Kick", tag = "moderation", index = 7)]
+impl Command for Ban {}
+
+#[traitable(skip)]
+pub struct Fixture;
+
+impl Command for Fixture {}
+
+#[traitable(skip)]
+impl Command for Session {}
+
+impl Command for Login {}
+
+pub trait Route {}
+
+pub fn get_self() {}
+
+#[traitable(route = "/users/self", handler = get_self)]
+impl Route for users::GetSelf {}
+
+#[traitable(method = "POST", route = "/ping")]
+pub struct Pong;
+
+#[traitable(route = "/pong", retries = 3)]
+impl Route for Pong {}
@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+
+use crate::Message as Msg;
+
+const BASE: u16 = 10;
+
+pub struct User;
+
+pub struct GetUsers {
+    pub limit: usize,
+    #[serde(default)]
+    pub after: Option<User>,
+}
+
+impl Msg for GetUsers {
+    type Response = HashMap<usize, Vec<User>>;
+    const NAME: &'static str = "get_users";
+    const OPCODE: u16 = BASE + 2;
+}
+
+pub struct GetSelf(pub Vec<Self>);
+
+impl Msg for GetSelf {
+    type Response = Result<self::User, super::Ping>;
+    const NAME: &'static str = "get_self";
+    const OPCODE: u16 = BASE + 1;
+}
+
+#[derive(Clone)]
+pub enum Status {
+    Online = BASE,
+    Away(std::time::Duration),
+    /// Not connected since the given status.
+    Offline { last_seen: Option<Box<Self>> },
+}
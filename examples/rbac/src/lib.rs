@@ -41,13 +41,13 @@ fn test() {
     use crate::RoleExt as _;
     use user::UserPermission;
 
-    assert_eq!(true, role.allows(&user_a, UserPermission::Create));
-    assert_eq!(true, role.allows(&user_a, UserPermission::Delete));
-    assert_eq!(true, role.allows(&user_a, UserPermission::View));
+    assert!(role.allows(&user_a, UserPermission::Create));
+    assert!(role.allows(&user_a, UserPermission::Delete));
+    assert!(role.allows(&user_a, UserPermission::View));
 
-    assert_eq!(false, role.allows(&user_b, UserPermission::Create));
-    assert_eq!(false, role.allows(&user_b, UserPermission::Delete));
-    assert_eq!(true, role.allows(&user_b, UserPermission::View));
+    assert!(!role.allows(&user_b, UserPermission::Create));
+    assert!(!role.allows(&user_b, UserPermission::Delete));
+    assert!(role.allows(&user_b, UserPermission::View));
 }
 
 pub trait AccessControl {
@@ -92,7 +92,7 @@ traitable::generate!(
     (AccessControl) => {
         #[derive(Debug, Clone, Deserialize, Serialize)]
         pub enum Rule {
-            $($ty (RuleInner<$ty_full>) )*
+            $( $ty(RuleInner<$ty_full>) ),*
         }
 
         $(
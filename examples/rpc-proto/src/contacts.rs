@@ -1,4 +1,6 @@
-use crate::Message;
+use traitable::traitable;
+
+use crate::{Event, Handler, Message};
 
 #[derive(Debug)]
 pub enum ContactType {
@@ -14,20 +16,48 @@ pub struct Contact {
     pub name: String,
 }
 
+impl Event for Contact {
+    const TOPIC: &'static str = "contacts";
+}
+
 #[derive(Debug)]
 pub struct GetContacts {
     pub user_id: usize,
 }
 
+#[traitable(route = "/contacts", method = "GET")]
 impl Message for GetContacts {
     type Response = Vec<Contact>;
+    const OPCODE: u16 = 10;
 }
 
+#[traitable(tag = "write")]
 #[derive(Debug)]
 pub struct AddContact {
     pub name: String,
 }
 
+#[traitable(route = "/contacts", index = 7)]
 impl Message for AddContact {
     type Response = Result<Contact, String>;
+    const OPCODE: u16 = 11;
+}
+
+pub struct ContactService;
+
+impl Handler<GetContacts> for ContactService {
+    fn handle(&self, _: GetContacts) -> Vec<Contact> {
+        Vec::new()
+    }
+}
+
+impl Handler<AddContact> for ContactService {
+    fn handle(&self, msg: AddContact) -> Result<Contact, String> {
+        Ok(Contact {
+            id: 1,
+            user_id: 1,
+            ty: ContactType::External,
+            name: msg.name,
+        })
+    }
 }
@@ -3,29 +3,140 @@ mod users;
 
 pub trait Message {
     type Response;
+    const OPCODE: u16;
+}
+
+pub trait Event {
+    const TOPIC: &'static str;
+}
+
+pub trait Handler<M: Message> {
+    fn handle(&self, msg: M) -> M::Response;
 }
 
 traitable::generate! {
-    (Message) => {
+    (Message join handler = Handler<$ty_full> order by $const_OPCODE) => {
         #[derive(Debug)]
         pub enum Request {
             $( $[R $index] ($ty_full), )*
         }
 
+        #[derive(Debug)]
+        pub enum Response {
+            $( $[R $index] ($assoc_Response), )*
+        }
+
+        impl Request {
+            pub fn opcode(&self) -> u16 {
+                match self {
+                    $( Request::$[R $index](_) => $const_OPCODE, )*
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $( Request::$[R $index](_) => $str[snake($ty)], )*
+                }
+            }
+
+            /// The HTTP method and route of the request, where requests that
+            /// have no method are sent with `POST`.
+            pub fn route(&self) -> (&'static str, &'static str) {
+                match self {
+                    $( Request::$[R $index](_) => ($meta_method:or("POST"), $meta_route), )*
+                }
+            }
+
+            /// Whether the request changes any data.
+            pub fn is_write(&self) -> bool {
+                match self {
+                    $( Request::$[R $index](_) => $if tag("write") { true } $else { false }, )*
+                }
+            }
+        }
+
+        pub fn dispatch(req: Request) -> Response {
+            match req {
+                $( Request::$[R $index](m) => Response::$[R $index]($handler.handle(m)), )*
+            }
+        }
+
+        /// Calls the given macro with the variants of `Request`.
+        #[macro_export]
+        macro_rules! with_requests {
+            ($$callback:ident) => {
+                $$callback!($( $[R $index] ),*)
+            };
+        }
+
         $(
-            impl Into<Request> for $ty_full {
-                fn into(self) -> Request {
-                    Request::$[R $index](self)
+            impl From<$ty_full> for Request {
+                fn from(msg: $ty_full) -> Request {
+                    Request::$[R $index](msg)
                 }
             }
         )*
     }
 }
 
+traitable::generate! {
+    msgs = Message order by $const_OPCODE, events = Event => {
+        #[derive(Debug)]
+        pub enum Packet {
+            $msgs( $[Request $ty]($ty_full), )*
+            $events( $[Event $ty]($ty_full), )*
+        }
+
+        impl Packet {
+            pub fn topic(&self) -> Option<&'static str> {
+                match self {
+                    $events( Packet::$[Event $ty](_) => Some(<$ty_full as Event>::TOPIC), )*
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
 #[test]
 fn test() {
-    let _: Request = contacts::AddContact {
+    let req: Request = contacts::AddContact {
         name: String::from("John Doe"),
     }
     .into();
+
+    assert_eq!(req.opcode(), 11);
+    assert!(matches!(req, Request::R7(_)));
+    assert_eq!(req.name(), "add_contact");
+    assert!(req.is_write());
+    assert_eq!(req.route(), ("POST", "/contacts"));
+    assert_eq!(
+        Request::from(users::GetSelf {}).route(),
+        ("GET", "/users/self")
+    );
+    assert!(!Request::from(users::GetSelf {}).is_write());
+
+    macro_rules! count {
+        ($($variant:ident),*) => {
+            [$(stringify!($variant)),*].len()
+        };
+    }
+
+    // `Echo` is skipped, so it does not take up a variant.
+    let _ = users::Echo;
+    assert_eq!(with_requests!(count), 4);
+
+    let Response::R7(contact) = dispatch(req) else {
+        panic!("expected the response to `AddContact`");
+    };
+
+    assert_eq!(contact.unwrap().name, "John Doe");
+
+    let packet = Packet::EventUser(users::User {
+        id: 1,
+        name: String::from("John Doe"),
+    });
+
+    assert_eq!(packet.topic(), Some("users"));
+    assert_eq!(Packet::RequestGetSelf(users::GetSelf {}).topic(), None);
 }
@@ -1,4 +1,4 @@
-use crate::Message;
+use crate::{Event, Handler, Message};
 
 #[derive(Debug)]
 pub struct User {
@@ -6,11 +6,17 @@ pub struct User {
     pub name: String,
 }
 
+impl Event for User {
+    const TOPIC: &'static str = "users";
+}
+
 #[derive(Debug)]
 pub struct GetSelf {}
 
+#[traitable::traitable(route = "/users/self", method = "GET")]
 impl Message for GetSelf {
     type Response = User;
+    const OPCODE: u16 = 1;
 }
 
 #[derive(Debug)]
@@ -20,6 +26,37 @@ pub struct SearchUsers {
     pub per_page: usize,
 }
 
+#[traitable::traitable(route = "/users/search", method = "GET")]
 impl Message for SearchUsers {
     type Response = Vec<User>;
+    const OPCODE: u16 = 2;
+}
+
+/// Only used in tests, so it is not a variant of `Request`.
+#[cfg(test)]
+#[traitable::traitable(skip)]
+#[derive(Debug)]
+pub struct Echo;
+
+#[cfg(test)]
+impl Message for Echo {
+    type Response = ();
+    const OPCODE: u16 = 99;
+}
+
+pub struct UserService;
+
+impl Handler<GetSelf> for UserService {
+    fn handle(&self, _: GetSelf) -> User {
+        User {
+            id: 1,
+            name: String::from("John Doe"),
+        }
+    }
+}
+
+impl Handler<SearchUsers> for UserService {
+    fn handle(&self, _: SearchUsers) -> Vec<User> {
+        Vec::new()
+    }
 }
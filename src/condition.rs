@@ -0,0 +1,346 @@
+use std::cmp::Ordering;
+
+use proc_macro2::{Ident, Span, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::{
+    ext::IdentExt,
+    parenthesized,
+    parse::{Parse, ParseStream, Parser},
+    punctuated::Punctuated,
+    Attribute, Lit, LitInt, LitStr, Path, Token,
+};
+
+use crate::{expr, Scope, Var};
+
+mod kw {
+    syn::custom_keyword!(first);
+    syn::custom_keyword!(last);
+    syn::custom_keyword!(attr);
+    syn::custom_keyword!(tag);
+}
+
+/// A boolean expression over the variables of a scope. For example:
+/// `$const_OPCODE >= 100 && !($const_NAME == "internal")`
+pub enum Condition {
+    /// True for the first item of the innermost repetition.
+    First,
+
+    /// True for the last item of the innermost repetition.
+    Last,
+
+    /// True if the innermost field, variant or implementer has an attribute
+    /// with the given path. For example: `attr(serde)`
+    Attr(Path),
+
+    /// True if the implementer has the given tag, from an attribute like
+    /// `#[traitable(tag = "admin")]`. For example: `tag("admin")`
+    Tag(LitStr),
+
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Compare(Operand, Comparison, Operand),
+
+    /// True if the operand is present, not empty and not `false`.
+    Test(Operand),
+}
+
+pub enum Operand {
+    Var(Var),
+    Tokens(TokenStream),
+}
+
+#[derive(Clone, Copy)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Parse for Condition {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut lhs = Self::parse_and(input)?;
+
+        while input.peek(Token![||]) {
+            input.parse::<Token![||]>()?;
+            lhs = Condition::Or(Box::new(lhs), Box::new(Self::parse_and(input)?));
+        }
+
+        Ok(lhs)
+    }
+}
+
+impl Condition {
+    fn parse_and(input: ParseStream) -> syn::Result<Self> {
+        let mut lhs = Self::parse_unary(input)?;
+
+        while input.peek(Token![&&]) {
+            input.parse::<Token![&&]>()?;
+            lhs = Condition::And(Box::new(lhs), Box::new(Self::parse_unary(input)?));
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Token![!]) {
+            input.parse::<Token![!]>()?;
+            return Ok(Condition::Not(Box::new(Self::parse_unary(input)?)));
+        }
+
+        if input.peek(syn::token::Paren) {
+            let inner;
+            parenthesized!(inner in input);
+            return inner.parse();
+        }
+
+        if input.peek(kw::first) {
+            input.parse::<kw::first>()?;
+            return Ok(Condition::First);
+        }
+
+        if input.peek(kw::last) {
+            input.parse::<kw::last>()?;
+            return Ok(Condition::Last);
+        }
+
+        if input.peek(kw::attr) && input.peek2(syn::token::Paren) {
+            input.parse::<kw::attr>()?;
+
+            let inner;
+            parenthesized!(inner in input);
+            return Ok(Condition::Attr(inner.call(Path::parse_mod_style)?));
+        }
+
+        if input.peek(kw::tag) && input.peek2(syn::token::Paren) {
+            input.parse::<kw::tag>()?;
+
+            let inner;
+            parenthesized!(inner in input);
+            return Ok(Condition::Tag(inner.parse()?));
+        }
+
+        let lhs: Operand = input.parse()?;
+
+        let cmp = if input.peek(Token![==]) {
+            input.parse::<Token![==]>()?;
+            Comparison::Eq
+        } else if input.peek(Token![!=]) {
+            input.parse::<Token![!=]>()?;
+            Comparison::Ne
+        } else if input.peek(Token![<=]) {
+            input.parse::<Token![<=]>()?;
+            Comparison::Le
+        } else if input.peek(Token![>=]) {
+            input.parse::<Token![>=]>()?;
+            Comparison::Ge
+        } else if input.peek(Token![<]) {
+            input.parse::<Token![<]>()?;
+            Comparison::Lt
+        } else if input.peek(Token![>]) {
+            input.parse::<Token![>]>()?;
+            Comparison::Gt
+        } else {
+            return Ok(Condition::Test(lhs));
+        };
+
+        Ok(Condition::Compare(lhs, cmp, input.parse()?))
+    }
+
+    pub fn eval(&self, scope: &Scope) -> syn::Result<bool> {
+        Ok(match self {
+            Condition::First => scope.position().is_some_and(|(index, _)| index == 0),
+            Condition::Last => scope
+                .position()
+                .is_some_and(|(index, count)| index + 1 == count),
+            Condition::Attr(path) => scope.attrs().is_some_and(|attrs| has_attr(attrs, path)),
+            Condition::Tag(tag) => scope.tags().is_some_and(|tags| has_tag(tags, tag)),
+            Condition::Not(c) => !c.eval(scope)?,
+            Condition::And(a, b) => a.eval(scope)? && b.eval(scope)?,
+            Condition::Or(a, b) => a.eval(scope)? || b.eval(scope)?,
+            Condition::Compare(lhs, cmp, rhs) => {
+                // Variables are not set for every implementer, like `$shape`
+                // for enums, so comparisons with unset variables are false.
+                let (Some(a), Some(b)) = (lhs.value(scope), rhs.value(scope)) else {
+                    return Ok(false);
+                };
+
+                let ord = match compare(&a, &b) {
+                    Ok(ord) => ord,
+                    Err(value) => {
+                        let operand = if std::ptr::eq(value, &a) { lhs } else { rhs };
+                        return Err(operand.not_an_integer(scope, value));
+                    }
+                };
+
+                match cmp {
+                    Comparison::Eq => ord.is_eq(),
+                    Comparison::Ne => ord.is_ne(),
+                    Comparison::Lt => ord.is_lt(),
+                    Comparison::Le => ord.is_le(),
+                    Comparison::Gt => ord.is_gt(),
+                    Comparison::Ge => ord.is_ge(),
+                }
+            }
+            Condition::Test(op) => match op {
+                Operand::Var(var) => scope.get(var).is_some_and(is_truthy),
+                Operand::Tokens(ts) => is_truthy(ts),
+            },
+        })
+    }
+}
+
+impl Parse for Operand {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Token![$]) {
+            return Ok(Operand::Var(input.parse()?));
+        }
+
+        if input.peek(Token![-]) && input.peek2(Lit) {
+            let minus = input.parse::<Token![-]>()?;
+            let lit = input.parse::<Lit>()?;
+
+            let mut tokens = minus.into_token_stream();
+            tokens.extend(lit.into_token_stream());
+
+            return Ok(Operand::Tokens(tokens));
+        }
+
+        if input.peek(Lit) {
+            return Ok(Operand::Tokens(input.parse::<Lit>()?.into_token_stream()));
+        }
+
+        // Keywords are allowed in paths, so that items can be compared with
+        // `$kind == enum`.
+        let mut tokens = input.call(Ident::parse_any)?.into_token_stream();
+
+        while input.peek(Token![::]) {
+            tokens.extend(input.parse::<Token![::]>()?.into_token_stream());
+            tokens.extend(input.call(Ident::parse_any)?.into_token_stream());
+        }
+
+        Ok(Operand::Tokens(tokens))
+    }
+}
+
+impl Operand {
+    /// Returns the value of the operand, or `None` for unset variables.
+    pub fn value(&self, scope: &Scope) -> Option<TokenStream> {
+        match self {
+            Operand::Var(var) => scope.get(var).cloned(),
+            // Bindings like `a` in `$product(a = Dto, b = Dto)` may be
+            // compared without `$`, as in `a != b`.
+            Operand::Tokens(ts) => match syn::parse2::<Ident>(ts.clone()) {
+                Ok(name) => Some(scope.binding_path(&name).unwrap_or(ts).clone()),
+                Err(_) => Some(ts.clone()),
+            },
+        }
+    }
+
+    fn not_an_integer(&self, scope: &Scope, value: &TokenStream) -> syn::Error {
+        match self {
+            Operand::Var(var) => {
+                not_an_integer(var.span(), &var.to_string(), scope.path_of(var), value)
+            }
+            Operand::Tokens(ts) => {
+                let span = ts
+                    .clone()
+                    .into_iter()
+                    .next()
+                    .map_or(Span::call_site(), |tt| tt.span());
+                syn::Error::new(span, format!("`{value}` is not an integer"))
+            }
+        }
+    }
+}
+
+impl Parse for Var {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![$]>()?;
+
+        let mut qualifiers = Vec::new();
+        let mut name = input.call(Ident::parse_any)?;
+
+        while input.peek(Token![.]) {
+            input.parse::<Token![.]>()?;
+            qualifiers.push(std::mem::replace(&mut name, input.call(Ident::parse_any)?));
+        }
+
+        Ok(Var { qualifiers, name })
+    }
+}
+
+fn has_attr(attrs: &TokenStream, path: &Path) -> bool {
+    let Ok(attrs) = Attribute::parse_outer.parse2(attrs.clone()) else {
+        return false;
+    };
+
+    attrs.iter().any(|attr| {
+        attr.path()
+            .segments
+            .iter()
+            .map(|s| &s.ident)
+            .eq(path.segments.iter().map(|s| &s.ident))
+    })
+}
+
+fn has_tag(tags: &TokenStream, tag: &LitStr) -> bool {
+    let Ok(tags) = Punctuated::<LitStr, Token![,]>::parse_terminated.parse2(tags.clone()) else {
+        return false;
+    };
+
+    tags.iter().any(|t| t.value() == tag.value())
+}
+
+fn is_truthy(value: &TokenStream) -> bool {
+    !value.is_empty() && value.to_string() != "false"
+}
+
+/// Compares two values numerically if both are integer literals, and by their
+/// textual representation if neither is. Values that cannot be compared, like
+/// an integer and a string or an expression like `BASE + 1`, whose value is
+/// not known, are returned as the error.
+pub fn compare<'a>(a: &'a TokenStream, b: &'a TokenStream) -> Result<Ordering, &'a TokenStream> {
+    match (as_int(a), as_int(b)) {
+        (Some(a), Some(b)) => Ok(a.cmp(&b)),
+        (Some(_), None) => Err(b),
+        (None, Some(_)) => Err(a),
+        (None, None) => match [a, b].into_iter().find(|v| expr::is_arithmetic(v)) {
+            Some(value) => Err(value),
+            None => Ok(a.to_string().cmp(&b.to_string())),
+        },
+    }
+}
+
+/// The error for a variable that is compared as an integer, but is not one.
+/// For example: "`$const_OPCODE` of `crate::Ping` is not an integer: `BASE + 1`"
+pub fn not_an_integer(
+    span: Span,
+    name: &str,
+    path: Option<String>,
+    value: &TokenStream,
+) -> syn::Error {
+    let of = path.map(|path| format!(" of `{path}`")).unwrap_or_default();
+
+    syn::Error::new(span, format!("`{name}`{of} is not an integer: `{value}`"))
+}
+
+pub fn as_int(tokens: &TokenStream) -> Option<i128> {
+    let mut iter = tokens.clone().into_iter().peekable();
+
+    let negative = matches!(iter.peek(), Some(TokenTree::Punct(p)) if p.as_char() == '-');
+
+    if negative {
+        iter.next();
+    }
+
+    let value = syn::parse2::<LitInt>(iter.collect())
+        .ok()?
+        .base10_parse::<i128>()
+        .ok()?;
+
+    Some(if negative { -value } else { value })
+}
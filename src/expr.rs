@@ -0,0 +1,161 @@
+use std::iter::Peekable;
+
+use proc_macro2::{
+    token_stream::IntoIter, Delimiter, Literal, Spacing, Span, TokenStream, TokenTree,
+};
+use quote::ToTokens;
+
+use crate::{condition::as_int, Scope};
+
+/// The integer types that values can be converted to with `$index:u16`.
+const INT_TYPES: &[&str] = &[
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+];
+
+/// Returns true if the contents of `$[...]` are an integer expression instead
+/// of an identifier, which is the case if they contain an arithmetic operator.
+/// For example: `$[index + 1]`
+pub fn is_arithmetic(input: &TokenStream) -> bool {
+    input.clone().into_iter().any(|tt| match tt {
+        TokenTree::Punct(p) => matches!(p.as_char(), '+' | '-' | '*' | '/' | '%'),
+        _ => false,
+    })
+}
+
+/// Evaluates an integer expression with `+`, `-`, `*`, `/`, `%` and parentheses.
+/// Variables may be used with or without `$`, like `$[count - index - 1]`.
+pub fn eval(scope: &Scope, input: TokenStream, span: Span) -> syn::Result<i128> {
+    let mut input = input.into_iter().peekable();
+    let value = sum(scope, &mut input, span)?;
+
+    match input.next() {
+        Some(tt) => Err(syn::Error::new(tt.span(), "expected an operator")),
+        None => Ok(value),
+    }
+}
+
+fn sum(scope: &Scope, input: &mut Peekable<IntoIter>, span: Span) -> syn::Result<i128> {
+    let mut value = product(scope, input, span)?;
+
+    while let Some(op) = take_operator(input, &['+', '-']) {
+        let rhs = product(scope, input, span)?;
+
+        value = match op {
+            '+' => value.checked_add(rhs),
+            _ => value.checked_sub(rhs),
+        }
+        .ok_or_else(|| syn::Error::new(span, "arithmetic overflow"))?;
+    }
+
+    Ok(value)
+}
+
+fn product(scope: &Scope, input: &mut Peekable<IntoIter>, span: Span) -> syn::Result<i128> {
+    let mut value = unary(scope, input, span)?;
+
+    while let Some(op) = take_operator(input, &['*', '/', '%']) {
+        let rhs = unary(scope, input, span)?;
+
+        if op != '*' && rhs == 0 {
+            return Err(syn::Error::new(span, "division by zero"));
+        }
+
+        value = match op {
+            '*' => value.checked_mul(rhs),
+            '/' => value.checked_div(rhs),
+            _ => value.checked_rem(rhs),
+        }
+        .ok_or_else(|| syn::Error::new(span, "arithmetic overflow"))?;
+    }
+
+    Ok(value)
+}
+
+fn unary(scope: &Scope, input: &mut Peekable<IntoIter>, span: Span) -> syn::Result<i128> {
+    if take_operator(input, &['-']).is_some() {
+        return Ok(-unary(scope, input, span)?);
+    }
+
+    let (tokens, span) = match input.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+            return eval(scope, g.stream(), g.span());
+        }
+        Some(TokenTree::Punct(p)) if p.as_char() == '$' => {
+            let Some(TokenTree::Ident(id)) = input.next() else {
+                return Err(syn::Error::new(p.span(), "expected a variable after `$`"));
+            };
+
+            let var = scope.parse_var(id, input);
+            (scope.lookup(&var)?, var.span())
+        }
+        Some(TokenTree::Ident(id)) => {
+            let var = scope.parse_var(id, input);
+            (scope.lookup(&var)?, var.span())
+        }
+        Some(TokenTree::Literal(lit)) => (TokenTree::Literal(lit.clone()).into(), lit.span()),
+        Some(tt) => return Err(syn::Error::new(tt.span(), "expected a number or variable")),
+        None => return Err(syn::Error::new(span, "expected a number or variable")),
+    };
+
+    as_int(&tokens).ok_or_else(|| syn::Error::new(span, format!("`{tokens}` is not an integer")))
+}
+
+fn take_operator(input: &mut Peekable<IntoIter>, operators: &[char]) -> Option<char> {
+    match input.peek() {
+        Some(TokenTree::Punct(p)) if operators.contains(&p.as_char()) => {
+            let op = p.as_char();
+            input.next();
+            Some(op)
+        }
+        _ => None,
+    }
+}
+
+/// Takes the type of `$index:u16` from the input, if it is next.
+pub fn parse_int_type(input: &mut Peekable<IntoIter>) -> Option<String> {
+    let mut lookahead = input.clone();
+
+    match (lookahead.next(), lookahead.next()) {
+        (Some(TokenTree::Punct(p)), Some(TokenTree::Ident(ty)))
+            if p.as_char() == ':'
+                && p.spacing() == Spacing::Alone
+                && INT_TYPES.iter().any(|t| ty == t) =>
+        {
+            input.next();
+            input.next();
+            Some(ty.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Creates an integer literal, with the given type as its suffix.
+pub fn int_literal(value: i128, ty: Option<&str>, span: Span) -> syn::Result<TokenStream> {
+    let Some(ty) = ty else {
+        return Ok(TokenTree::Literal(Literal::i128_unsuffixed(value)).into());
+    };
+
+    let fits = match ty {
+        "u8" => u8::try_from(value).is_ok(),
+        "u16" => u16::try_from(value).is_ok(),
+        "u32" => u32::try_from(value).is_ok(),
+        "u64" => u64::try_from(value).is_ok(),
+        "u128" => u128::try_from(value).is_ok(),
+        "usize" => usize::try_from(value).is_ok(),
+        "i8" => i8::try_from(value).is_ok(),
+        "i16" => i16::try_from(value).is_ok(),
+        "i32" => i32::try_from(value).is_ok(),
+        "i64" => i64::try_from(value).is_ok(),
+        "isize" => isize::try_from(value).is_ok(),
+        _ => true,
+    };
+
+    if !fits {
+        return Err(syn::Error::new(
+            span,
+            format!("`{value}` is out of range for `{ty}`"),
+        ));
+    }
+
+    Ok(syn::LitInt::new(&format!("{value}{ty}"), span).to_token_stream())
+}
@@ -0,0 +1,167 @@
+use proc_macro2::{Ident, Span};
+
+/// The functions that can be called in `$[...]`, together with the number of
+/// arguments they take.
+const FUNCTIONS: &[(&str, usize)] = &[
+    ("snake", 1),
+    ("camel", 1),
+    ("pascal", 1),
+    ("shouty", 1),
+    ("kebab", 1),
+    ("trim_prefix", 2),
+    ("trim_suffix", 2),
+    ("replace", 3),
+];
+
+/// Keywords that can only be used as identifiers in their raw form.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+pub fn is_function(name: &Ident) -> bool {
+    FUNCTIONS.iter().any(|(f, _)| name == f)
+}
+
+/// Calls the function with the given name. For example: `snake(GetSelf)`
+/// returns `get_self` and `trim_suffix(GetUserRequest, Request)` returns
+/// `GetUser`.
+pub fn call(name: &Ident, args: &[String]) -> syn::Result<String> {
+    let function = name.to_string();
+
+    let Some(&(_, arity)) = FUNCTIONS.iter().find(|(f, _)| *f == function) else {
+        return Err(syn::Error::new(
+            name.span(),
+            format!("unknown function `{function}`"),
+        ));
+    };
+
+    if args.len() != arity {
+        return Err(syn::Error::new(
+            name.span(),
+            format!(
+                "`{function}` takes {arity} argument{}, but {} were given",
+                if arity == 1 { "" } else { "s" },
+                args.len(),
+            ),
+        ));
+    }
+
+    Ok(match function.as_str() {
+        "snake" => join(&args[0], "_", str::to_lowercase),
+        "shouty" => join(&args[0], "_", str::to_uppercase),
+        "kebab" => join(&args[0], "-", str::to_lowercase),
+        "pascal" => join(&args[0], "", capitalize),
+        "camel" => {
+            let mut words = words(&args[0]).into_iter();
+
+            words
+                .next()
+                .map(|w| w.to_lowercase())
+                .into_iter()
+                .chain(words.map(|w| capitalize(&w)))
+                .collect()
+        }
+        "trim_prefix" => args[0]
+            .strip_prefix(args[1].as_str())
+            .unwrap_or(&args[0])
+            .to_string(),
+        "trim_suffix" => args[0]
+            .strip_suffix(args[1].as_str())
+            .unwrap_or(&args[0])
+            .to_string(),
+        "replace" => args[0].replace(args[1].as_str(), &args[2]),
+        _ => unreachable!(),
+    })
+}
+
+/// Splits a name into its words, at underscores and other separators as well as
+/// at changes of case. For example: `HTTPServer_v2` becomes `HTTP`, `Server`
+/// and `v2`.
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+
+            continue;
+        }
+
+        if let Some(prev) = word.chars().last() {
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+
+            // `getSelf` and `v2Request` are split before the uppercase letter,
+            // and `HTTPServer` before the last one of the uppercase letters.
+            let boundary = c.is_uppercase()
+                && (prev.is_lowercase()
+                    || prev.is_numeric()
+                    || (prev.is_uppercase() && next_is_lower));
+
+            if boundary {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+
+        word.push(c);
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+fn join(name: &str, separator: &str, case: impl Fn(&str) -> String) -> String {
+    words(name)
+        .iter()
+        .map(|w| case(w))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Turns a name into a valid identifier. Characters that are not allowed in
+/// identifiers are replaced with `_`, names that start with a digit are
+/// prefixed with `_` and keywords become raw identifiers.
+pub fn to_ident(name: &str, span: Span) -> syn::Result<Ident> {
+    let mut ident: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+
+    if ident.is_empty() {
+        return Err(syn::Error::new(span, "the identifier is empty"));
+    }
+
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+
+    Ok(match KEYWORDS.contains(&ident.as_str()) {
+        true => Ident::new_raw(&ident, Span::call_site()),
+        false => Ident::new(&ident, Span::call_site()),
+    })
+}
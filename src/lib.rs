@@ -1,41 +1,172 @@
+mod condition;
+mod expr;
+mod functions;
+mod query;
+
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     iter::{once, Peekable},
+    rc::Rc,
 };
 
 use proc_macro2::{
-    token_stream::IntoIter, Delimiter, Group, Ident, Literal, Punct, Span, TokenStream, TokenTree,
+    token_stream::IntoIter, Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream,
+    TokenTree,
 };
+use quote::{quote, ToTokens};
 use syn::{
     braced, parenthesized,
-    parse::{Parse, ParseStream},
+    parse::{Parse, ParseStream, Parser},
     parse_macro_input,
+    punctuated::Punctuated,
     token::FatArrow,
+    Token,
+};
+use traitable_core::{
+    cargo::manifest_from_env, parse, DeriveMap, Field, Implementer, Kind, Options, PathFilter,
+    Shape, Variant,
+};
+
+use crate::{
+    condition::Condition,
+    query::{Binding, Query},
 };
-use traitable_core::{cargo::entry_file_from_env, parse, Implementer};
+
+mod kw {
+    syn::custom_keyword!(derive);
+}
 
 #[proc_macro]
 pub fn generate(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as GenerateInput);
 
-    let entry = entry_file_from_env().unwrap();
-    let result = parse(entry).unwrap();
-    let implementers = result.lookup(&input.trait_name.to_string());
-
-    Context::from_iter(implementers)
-        .translate(input.body)
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
         .into()
 }
 
+/// Configures how a type or impl block is seen by `generate!`, and otherwise
+/// leaves it unchanged. For example: `#[traitable(skip)]`,
+/// `#[traitable(rename = "Login")]` or `#[traitable(tag = "admin")]`
+#[proc_macro_attribute]
+pub fn traitable(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let mut options = Options::default();
+    let parser = syn::meta::parser(|meta| options.parse_meta(meta));
+
+    match parser.parse(args) {
+        Ok(()) => item,
+        Err(e) => {
+            let mut item = TokenStream::from(item);
+            item.extend(e.into_compile_error());
+            item.into()
+        }
+    }
+}
+
+fn expand(input: GenerateInput) -> syn::Result<TokenStream> {
+    let error = |e: traitable_core::Error| syn::Error::new(Span::call_site(), e);
+
+    let manifest = manifest_from_env().map_err(error)?;
+
+    let mut derives = manifest.derives();
+    derives.extend(input.derives);
+
+    let result = parse(manifest.get_entry().map_err(error)?)
+        .map_err(error)?
+        .with_derives(derives);
+
+    let mut ctx = Context::default();
+
+    for (name, query) in input.queries {
+        let items = query.select(|name, paths| result.lookup_in(name, paths))?;
+
+        match name {
+            Some(name) => {
+                ctx.sources.insert(name.to_string(), numbered(items));
+            }
+            None => ctx = Context::from_iter(items),
+        }
+    }
+
+    ctx.lookup = Some(Rc::new(move |name: &str, paths: &PathFilter| {
+        result.lookup_in(name, paths)
+    }));
+    ctx.translate(input.body)
+}
+
 struct GenerateInput {
-    trait_name: Ident,
+    /// Traits that are implemented by custom derives, in addition to the ones
+    /// from `[package.metadata.traitable]`. For example:
+    /// `derive Model => Entity + Persist;`
+    derives: DeriveMap,
+
+    /// The queries together with the names of the sources they are bound to.
+    /// For example: `msgs = Message, events = Event => { ... }`
+    /// The query of the `(Message) => { ... }` form has no name, and its
+    /// implementers are repeated with `$(...)`.
+    queries: Vec<(Option<Ident>, Query)>,
     body: TokenStream,
 }
 
 impl Parse for GenerateInput {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let sig;
-        parenthesized!(sig in input);
+        let mut derives = DeriveMap::default();
+
+        while input.peek(kw::derive) {
+            input.parse::<kw::derive>()?;
+
+            let path = input.call(syn::Path::parse_mod_style)?;
+
+            input.parse::<FatArrow>()?;
+
+            let traits = Punctuated::<Ident, Token![+]>::parse_separated_nonempty(input)?;
+
+            input.parse::<Token![;]>()?;
+
+            derives.insert(
+                &path
+                    .segments
+                    .iter()
+                    .map(|s| s.ident.to_string())
+                    .collect::<Vec<_>>()
+                    .join("::"),
+                traits.iter().map(Ident::to_string),
+            );
+        }
+
+        let mut queries = Vec::new();
+
+        if input.peek(syn::token::Paren) {
+            let sig;
+            parenthesized!(sig in input);
+            queries.push((None, sig.parse()?));
+        } else {
+            loop {
+                let Binding { name, query } = input.parse()?;
+
+                if queries.iter().any(|(n, _)| n.as_ref() == Some(&name)) {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        format!("`{name}` is bound more than once"),
+                    ));
+                }
+
+                queries.push((Some(name), query));
+
+                if !input.peek(Token![,]) {
+                    break;
+                }
+
+                input.parse::<Token![,]>()?;
+
+                if input.peek(FatArrow) {
+                    break;
+                }
+            }
+        }
 
         input.parse::<FatArrow>()?;
 
@@ -43,13 +174,14 @@ impl Parse for GenerateInput {
         braced!(body in input);
 
         Ok(GenerateInput {
-            trait_name: sig.parse()?,
+            derives,
+            queries,
             body: body.parse()?,
         })
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct Context {
     /// Variables that can be used in the current context. For example: $count
     /// or $name.
@@ -58,63 +190,564 @@ struct Context {
     /// A group of child Context instances that can be used for repetition in
     /// the current context. For example: $( $path $name, )*
     repeatable: Option<Vec<Context>>,
+
+    /// Named groups of child Context instances, which can be repeated by their
+    /// name. For example: $modules( $mod_path, )*
+    sources: HashMap<String, Vec<Context>>,
+
+    /// Contexts that variables can be looked up in by qualifying them with the
+    /// name of the binding. For example: $prev.ty or $next.ty
+    bindings: HashMap<String, Context>,
+
+    /// Looks up the implementers of a trait, for queries that are made in the
+    /// template. For example: $product(a = Dto, b = Dto)( ... )*
+    lookup: Option<Lookup>,
+
+    /// The index given with `#[traitable(index = N)]`, which `$index` is set
+    /// to instead of the position of the context.
+    index: Option<usize>,
 }
 
-impl FromIterator<Implementer> for Context {
-    fn from_iter<T: IntoIterator<Item = Implementer>>(iter: T) -> Self {
-        let implementers: Vec<_> = iter
-            .into_iter()
-            .enumerate()
-            .map(|(index, imp)| {
-                let mut ty_full: Vec<_> = imp.path.split("::").collect();
-                ty_full.push(&imp.name);
+type Lookup = Rc<dyn Fn(&str, &PathFilter) -> Vec<Implementer>>;
 
-                let ty_full: Vec<_> = ty_full
-                    .iter()
-                    .flat_map(|seg| {
-                        vec![
-                            TokenTree::Ident(Ident::new(seg, Span::call_site())),
-                            TokenTree::Punct(Punct::new(':', proc_macro2::Spacing::Joint)),
-                            TokenTree::Punct(Punct::new(':', proc_macro2::Spacing::Alone)),
-                        ]
-                    })
-                    .take(ty_full.len() * 3 - 2)
-                    .collect();
-
-                Context {
-                    vars: HashMap::from_iter([
-                        (
-                            "index".into(),
-                            TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(index))),
-                        ),
-                        (
-                            "ty".into(),
-                            TokenStream::from(TokenTree::Ident(Ident::new(
-                                &imp.name,
-                                Span::call_site(),
-                            ))),
-                        ),
-                        ("ty_full".into(), TokenStream::from_iter(ty_full)),
-                    ]),
-                    repeatable: None,
-                }
+impl From<Implementer> for Context {
+    fn from(imp: Implementer) -> Self {
+        let mut ty_full: Vec<_> = imp.path.split("::").collect();
+        ty_full.push(&imp.name);
+
+        let ty_full: Vec<_> = ty_full
+            .iter()
+            .flat_map(|seg| {
+                vec![
+                    TokenTree::Ident(Ident::new(seg, Span::call_site())),
+                    TokenTree::Punct(Punct::new(':', proc_macro2::Spacing::Joint)),
+                    TokenTree::Punct(Punct::new(':', proc_macro2::Spacing::Alone)),
+                ]
             })
+            .take(ty_full.len() * 3 - 2)
             .collect();
 
+        // `#[traitable(rename = "...")]` changes the name, but not the path
+        // that refers to the type. Invalid names are rejected by the attribute.
+        let (name, ty) = match imp.rename.as_deref().map(syn::parse_str::<Ident>) {
+            Some(Ok(ident)) => (imp.rename.as_deref().unwrap(), ident),
+            _ => (imp.name.as_str(), Ident::new(&imp.name, Span::call_site())),
+        };
+
+        let mut vars = HashMap::from_iter([
+            ("ty".into(), TokenStream::from(TokenTree::Ident(ty))),
+            ("ty_full".into(), TokenStream::from_iter(ty_full)),
+            (
+                "ty_str".into(),
+                TokenStream::from(TokenTree::Literal(Literal::string(name))),
+            ),
+            (
+                "path_str".into(),
+                TokenStream::from(TokenTree::Literal(Literal::string(&format!(
+                    "{}::{}",
+                    imp.path, imp.name
+                )))),
+            ),
+            ("mod_path".into(), imp.path.parse().unwrap()),
+            ("is_unsafe".into(), bool_tokens(imp.is_unsafe)),
+            ("is_default".into(), bool_tokens(imp.is_default)),
+        ]);
+
+        // The attribute is left out entirely for unconditional implementers, so
+        // that `$cfg` can be put in front of any item.
+        vars.insert(
+            "cfg".into(),
+            match &imp.cfg {
+                Some(cfg) => {
+                    let cfg: TokenStream = cfg.parse().unwrap();
+                    quote!(#[cfg(#cfg)])
+                }
+                None => TokenStream::new(),
+            },
+        );
+
+        for (name, tokens) in &imp.assoc {
+            vars.insert(format!("assoc_{name}"), tokens.parse().unwrap());
+        }
+
+        for (name, tokens) in &imp.consts {
+            vars.insert(format!("const_{name}"), tokens.parse().unwrap());
+        }
+
+        if let Some(kind) = imp.kind {
+            let kind = match kind {
+                Kind::Struct => "struct",
+                Kind::Enum => "enum",
+            };
+
+            vars.insert("kind".into(), ident_tokens(kind));
+        }
+
+        if let Some(shape) = imp.shape {
+            vars.insert("shape".into(), shape_tokens(shape));
+        }
+
+        vars.insert("attrs".into(), attrs_tokens(&imp.attrs));
+        for (key, value) in &imp.meta {
+            vars.insert(format!("meta_{key}"), value.parse().unwrap());
+        }
+
+        vars.insert(
+            "tags".into(),
+            imp.tags
+                .iter()
+                .map(|tag| Literal::string(tag))
+                .collect::<Punctuated<_, Token![,]>>()
+                .into_token_stream(),
+        );
+
+        Context {
+            vars,
+            index: imp.index,
+            sources: HashMap::from_iter([
+                (
+                    "fields".into(),
+                    numbered(imp.fields.iter().enumerate().map(field)),
+                ),
+                (
+                    "variants".into(),
+                    numbered(imp.variants.iter().enumerate().map(variant)),
+                ),
+            ]),
+            ..Default::default()
+        }
+    }
+}
+
+fn ident_tokens(name: &str) -> TokenStream {
+    TokenStream::from(TokenTree::Ident(Ident::new(name, Span::call_site())))
+}
+
+fn attrs_tokens(attrs: &[String]) -> TokenStream {
+    attrs
+        .iter()
+        .map(|a| a.parse::<TokenStream>().unwrap())
+        .collect()
+}
+
+fn shape_tokens(shape: Shape) -> TokenStream {
+    ident_tokens(match shape {
+        Shape::Named => "named",
+        Shape::Tuple => "tuple",
+        Shape::Unit => "unit",
+    })
+}
+
+/// Creates the context of an enum variant, which can be repeated with
+/// `$variants(...)`. `$variant_discriminant` is empty for variants without an
+/// explicit discriminant, and the fields of the variant can be repeated with
+/// `$fields(...)`.
+fn variant((index, variant): (usize, &Variant)) -> Context {
+    Context {
+        vars: HashMap::from_iter([
+            ("variant".into(), ident_tokens(&variant.name)),
+            (
+                "variant_index".into(),
+                TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(index))),
+            ),
+            ("variant_attrs".into(), attrs_tokens(&variant.attrs)),
+            ("variant_shape".into(), shape_tokens(variant.shape)),
+            (
+                "variant_discriminant".into(),
+                variant
+                    .discriminant
+                    .as_deref()
+                    .map(|d| d.parse().unwrap())
+                    .unwrap_or_default(),
+            ),
+        ]),
+        sources: HashMap::from_iter([(
+            "fields".into(),
+            numbered(variant.fields.iter().enumerate().map(field)),
+        )]),
+        ..Default::default()
+    }
+}
+
+/// Creates the context of a struct field, which can be repeated with
+/// `$fields(...)`. Fields of tuple structs use their index as `$field_name`, so
+/// that `self.$field_name` works for all shapes.
+fn field((index, field): (usize, &Field)) -> Context {
+    let index = TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(index)));
+
+    let name = match &field.name {
+        Some(name) => ident_tokens(name),
+        None => index.clone(),
+    };
+
+    Context {
+        vars: HashMap::from_iter([
+            ("field_name".into(), name),
+            ("field_ty".into(), field.ty.parse().unwrap()),
+            ("field_index".into(), index),
+            ("field_attrs".into(), attrs_tokens(&field.attrs)),
+        ]),
+        ..Default::default()
+    }
+}
+
+fn bool_tokens(value: bool) -> TokenStream {
+    TokenStream::from(TokenTree::Ident(Ident::new(
+        if value { "true" } else { "false" },
+        Span::call_site(),
+    )))
+}
+
+impl FromIterator<Implementer> for Context {
+    fn from_iter<T: IntoIterator<Item = Implementer>>(iter: T) -> Self {
+        iter.into_iter().map(Context::from).collect()
+    }
+}
+
+impl FromIterator<Context> for Context {
+    fn from_iter<T: IntoIterator<Item = Context>>(iter: T) -> Self {
+        let items = numbered(iter);
+        let modules = numbered(modules(&items));
+
         Context {
             vars: HashMap::from_iter([(
                 "count".into(),
-                TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(
-                    implementers.len(),
-                ))),
+                TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(items.len()))),
             )]),
-            repeatable: Some(implementers),
+            sources: HashMap::from_iter([
+                ("implementers".into(), items.clone()),
+                ("modules".into(), modules),
+            ]),
+            repeatable: Some(items),
+            ..Default::default()
+        }
+    }
+}
+
+/// Sets `$index`, `$rev_index`, `$count`, `$is_first` and `$is_last` on each of
+/// the given contexts. Contexts with an explicit index keep it, and the others
+/// are numbered in order with the indices that are not taken.
+fn numbered(items: impl IntoIterator<Item = Context>) -> Vec<Context> {
+    let mut items: Vec<_> = items.into_iter().collect();
+    let count = items.len();
+
+    let taken: HashSet<usize> = items.iter().filter_map(|ctx| ctx.index).collect();
+    let mut free = (0..).filter(|i| !taken.contains(i));
+
+    for ctx in items.iter_mut() {
+        let index = match ctx.index {
+            Some(index) => index,
+            None => free.next().unwrap(),
+        };
+
+        ctx.vars.insert(
+            "index".into(),
+            TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(index))),
+        );
+        ctx.vars.insert(
+            "count".into(),
+            TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(count))),
+        );
+    }
+
+    positioned(items)
+}
+
+/// Sets `$rev_index`, `$is_first` and `$is_last` on each of the given contexts,
+/// from their positions.
+fn positioned(items: impl IntoIterator<Item = Context>) -> Vec<Context> {
+    let mut items: Vec<_> = items.into_iter().collect();
+    let count = items.len();
+
+    for (position, ctx) in items.iter_mut().enumerate() {
+        ctx.vars.insert(
+            "rev_index".into(),
+            TokenStream::from(TokenTree::Literal(Literal::usize_unsuffixed(
+                count - position - 1,
+            ))),
+        );
+        ctx.vars
+            .insert("is_first".into(), bool_tokens(position == 0));
+        ctx.vars
+            .insert("is_last".into(), bool_tokens(position + 1 == count));
+    }
+
+    items
+}
+
+/// Groups the contexts of implementers by the module they are found in. Each
+/// module can be repeated with `$modules(...)` and has the variables `$mod` and
+/// `$mod_path`, while its implementers are repeated with `$implementers(...)`
+/// or `$(...)`.
+fn modules(items: &[Context]) -> Vec<Context> {
+    let mut modules: Vec<(String, Vec<Context>)> = Vec::new();
+
+    for ctx in items {
+        let Some(path) = ctx.var("mod_path") else {
+            continue;
+        };
+
+        let key = path.to_string();
+
+        match modules.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, module)) => module.push(ctx.clone()),
+            None => modules.push((key, vec![ctx.clone()])),
         }
     }
+
+    modules
+        .into_iter()
+        .map(|(_, items)| {
+            let mod_path = items[0].vars["mod_path"].clone();
+            let name = mod_path.clone().into_iter().last().unwrap();
+            let items = numbered(items);
+
+            Context {
+                vars: HashMap::from_iter([
+                    ("mod".into(), TokenStream::from(name)),
+                    ("mod_path".into(), mod_path),
+                ]),
+                sources: HashMap::from_iter([("implementers".into(), items.clone())]),
+                repeatable: Some(items),
+                ..Default::default()
+            }
+        })
+        .collect()
 }
 
 impl Context {
-    pub fn translate(&self, input: TokenStream) -> TokenStream {
+    pub fn var(&self, name: &str) -> Option<&TokenStream> {
+        self.vars.get(name)
+    }
+
+    /// Returns true if the context is the one of an implementer, rather than of
+    /// a module, field or variant.
+    fn is_implementer(&self) -> bool {
+        self.vars.contains_key("path_str")
+    }
+
+    pub fn translate(&self, input: TokenStream) -> syn::Result<TokenStream> {
+        Scope::new(self).translate(input)
+    }
+}
+
+/// A reference to a variable, optionally qualified with the scopes it is looked
+/// up in. For example: `$index` or `$outer.index`
+struct Var {
+    pub qualifiers: Vec<Ident>,
+    pub name: Ident,
+}
+
+impl Var {
+    fn span(&self) -> Span {
+        self.qualifiers.first().unwrap_or(&self.name).span()
+    }
+}
+
+impl std::fmt::Display for Var {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "$")?;
+
+        for q in &self.qualifiers {
+            write!(f, "{q}.")?;
+        }
+
+        write!(f, "{}", self.name)
+    }
+}
+
+/// A context together with the contexts of all surrounding repetitions. Variables
+/// that are not defined in the context itself are looked up in its parents.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    ctx: &'a Context,
+    parent: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(ctx: &'a Context) -> Self {
+        Scope { ctx, parent: None }
+    }
+
+    fn child(&'a self, ctx: &'a Context) -> Self {
+        Scope {
+            ctx,
+            parent: Some(self),
+        }
+    }
+
+    fn is_qualifier(&self, name: &Ident) -> bool {
+        self.qualified(name).is_some()
+    }
+
+    /// Returns the scope that a qualifier of a variable refers to. Bound
+    /// contexts, like `$prev` in `$pairs(...)`, are looked up on their own.
+    fn qualified(&self, qualifier: &Ident) -> Option<Scope<'a>> {
+        if qualifier == "outer" {
+            return self.parent.copied();
+        }
+
+        self.binding(qualifier).map(Scope::new)
+    }
+
+    fn binding(&self, name: &Ident) -> Option<&'a Context> {
+        let name = name.to_string();
+        let mut scope = Some(self);
+
+        while let Some(s) = scope {
+            if let Some(ctx) = s.ctx.bindings.get(&name) {
+                return Some(ctx);
+            }
+
+            scope = s.parent;
+        }
+
+        None
+    }
+
+    /// Returns the full path of the implementer that is bound to the name,
+    /// which `$a` refers to in `$product(a = Dto, b = Dto)`.
+    pub fn binding_path(&self, name: &Ident) -> Option<&'a TokenStream> {
+        self.binding(name)?.var("ty_full")
+    }
+
+    pub fn get(&self, var: &Var) -> Option<&'a TokenStream> {
+        let mut scope = *self;
+
+        for q in &var.qualifiers {
+            scope = scope.qualified(q)?;
+        }
+
+        let name = var.name.to_string();
+
+        // The options and items of an implementer, like `$meta_route`, are not
+        // taken from an enclosing implementer when it does not have them.
+        let own = ["meta_", "assoc_", "const_"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+
+        let mut scope = Some(&scope);
+
+        while let Some(s) = scope {
+            if let Some(value) = s.ctx.var(&name) {
+                return Some(value);
+            }
+
+            if own && s.ctx.is_implementer() {
+                break;
+            }
+
+            scope = s.parent;
+        }
+
+        match var.qualifiers.is_empty() {
+            true => self.binding_path(&var.name),
+            false => None,
+        }
+    }
+
+    /// Returns the named repetition source from the closest scope that has it.
+    fn source(&self, name: &str) -> Option<&'a [Context]> {
+        let mut scope = Some(self);
+
+        while let Some(s) = scope {
+            if let Some(items) = s.ctx.sources.get(name) {
+                return Some(items);
+            }
+
+            scope = s.parent;
+        }
+
+        None
+    }
+
+    /// Returns the position and the count of the innermost repeated item.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        let mut scope = Some(self);
+
+        while let Some(s) = scope {
+            // `$index` may be given explicitly, so the position is taken from
+            // `$rev_index` instead.
+            if let (Some(rev_index), Some(count)) = (s.ctx.var("rev_index"), s.ctx.var("count")) {
+                let rev_index: usize = rev_index.to_string().parse().ok()?;
+                let count: usize = count.to_string().parse().ok()?;
+
+                return Some((count.checked_sub(rev_index + 1)?, count));
+            }
+
+            scope = s.parent;
+        }
+
+        None
+    }
+
+    /// Returns the tags of the innermost implementer.
+    pub fn tags(&self) -> Option<&'a TokenStream> {
+        self.get(&Var {
+            qualifiers: Vec::new(),
+            name: Ident::new("tags", Span::call_site()),
+        })
+    }
+
+    /// Returns the attributes of the innermost field, variant or implementer.
+    pub fn attrs(&self) -> Option<&'a TokenStream> {
+        let mut scope = Some(self);
+
+        while let Some(s) = scope {
+            let attrs = ["field_attrs", "variant_attrs", "attrs"]
+                .into_iter()
+                .find_map(|name| s.ctx.var(name));
+
+            if attrs.is_some() {
+                return attrs;
+            }
+
+            scope = s.parent;
+        }
+
+        None
+    }
+
+    /// Returns the full path of the implementer that a variable belongs to, to
+    /// name it in errors.
+    pub fn path_of(&self, var: &Var) -> Option<String> {
+        let path = self.get(&Var {
+            qualifiers: var.qualifiers.clone(),
+            name: Ident::new("path_str", Span::call_site()),
+        })?;
+
+        Some(syn::parse2::<syn::LitStr>(path.clone()).ok()?.value())
+    }
+
+    pub fn lookup(&self, var: &Var) -> syn::Result<TokenStream> {
+        if let Some(value) = self.get(var) {
+            return Ok(value.clone());
+        }
+
+        let name = var.name.to_string();
+
+        let Some(key) = name.strip_prefix("meta_") else {
+            return Err(syn::Error::new(
+                var.span(),
+                format!("unknown variable `{var}`"),
+            ));
+        };
+
+        let ty = self
+            .path_of(var)
+            .map(|ty| format!(" of `{ty}`"))
+            .unwrap_or_default();
+
+        Err(syn::Error::new(
+            var.span(),
+            format!(
+                "`{key}` is missing in the `#[traitable(...)]` attribute{ty}, \
+                 use `{var}:or(...)` to give a default"
+            ),
+        ))
+    }
+
+    pub fn translate(&self, input: TokenStream) -> syn::Result<TokenStream> {
         let mut tokens = input.into_iter().peekable();
         let mut output = TokenStream::new();
 
@@ -125,13 +758,12 @@ impl Context {
 
             match tt {
                 TokenTree::Punct(p) if p.as_char() == '$' => {
-                    output.extend(self.translate_expression(&mut tokens));
+                    output.extend(self.translate_expression(&p, &mut tokens)?);
                 }
                 TokenTree::Group(g) => {
-                    output.extend(once(TokenTree::Group(Group::new(
-                        g.delimiter(),
-                        self.translate(g.stream()),
-                    ))));
+                    let mut group = Group::new(g.delimiter(), self.translate(g.stream())?);
+                    group.set_span(g.span());
+                    output.extend(once(TokenTree::Group(group)));
                 }
                 other => {
                     output.extend(once(other));
@@ -139,86 +771,627 @@ impl Context {
             }
         }
 
-        output
+        Ok(output)
     }
 
-    fn translate_expression(&self, input: &mut Peekable<IntoIter>) -> TokenStream {
+    fn translate_expression(
+        &self,
+        dollar: &Punct,
+        input: &mut Peekable<IntoIter>,
+    ) -> syn::Result<TokenStream> {
         let Some(tt) = input.next() else {
-            panic!();
+            return Err(syn::Error::new(
+                dollar.span(),
+                "expected a variable, `$[...]` or `$(...)` after `$`",
+            ));
         };
 
         match tt {
-            TokenTree::Ident(id) => self.vars.get(&id.to_string()).cloned().unwrap(),
-            TokenTree::Group(gr) => match gr.delimiter() {
-                Delimiter::Bracket => {
-                    TokenStream::from(TokenTree::Ident(self.make_ident(gr.stream())))
+            // `$$` is a literal `$`, for example to define `macro_rules!`.
+            TokenTree::Punct(p) if p.as_char() == '$' => Ok(TokenStream::from(TokenTree::Punct(p))),
+            TokenTree::Ident(id) if id == "raw" => match parse_group(input, Delimiter::Brace) {
+                Some(gr) => Ok(gr.stream()),
+                None => self.variable(id, input),
+            },
+            TokenTree::Ident(id) if id == "if" => self.conditional(input),
+            TokenTree::Ident(id) if id == "str" => {
+                let Some(gr) = parse_group(input, Delimiter::Bracket) else {
+                    return self.variable(id, input);
+                };
+
+                let mut lit = Literal::string(&self.make_string(gr.stream())?);
+                lit.set_span(gr.span());
+
+                Ok(TokenStream::from(TokenTree::Literal(lit)))
+            }
+            TokenTree::Ident(id) if id == "else" => Err(syn::Error::new(
+                id.span(),
+                "`$else` without a preceding `$if`",
+            )),
+            TokenTree::Ident(id) => match (self.source(&id.to_string()), input.peek()) {
+                (Some(items), Some(TokenTree::Group(gr)))
+                    if gr.delimiter() == Delimiter::Parenthesis =>
+                {
+                    let gr = gr.clone();
+                    input.next();
+                    self.repeat(items, &gr, input)
                 }
-                Delimiter::Parenthesis => {
-                    let Some(ref rep) = self.repeatable else {
-                        panic!()
-                    };
+                (Some(items), Some(TokenTree::Punct(_))) => match parse_mode(input) {
+                    Some((mode, gr)) => self.iterate(items, &mode, &gr, input),
+                    None => self.variable(id, input),
+                },
+                (None, Some(TokenTree::Group(gr)))
+                    if gr.delimiter() == Delimiter::Parenthesis
+                        && matches!(id.to_string().as_str(), "rev" | "pairs" | "chunks") =>
+                {
+                    let gr = gr.clone();
+                    input.next();
 
-                    let mut out = TokenStream::new();
+                    let Some(ref items) = self.ctx.repeatable else {
+                        let mut sources: Vec<_> = self.ctx.sources.keys().collect();
+                        sources.sort();
 
-                    for ctx in rep {
-                        out.extend(ctx.translate(gr.stream()));
-                    }
+                        return Err(syn::Error::new(
+                            id.span(),
+                            match sources.first() {
+                                Some(source) => format!(
+                                    "`${id}` only repeats the implementers of an unnamed \
+                                     query, use `${source}:{id}(...)` for named sources"
+                                ),
+                                None => "nothing to repeat in this context".into(),
+                            },
+                        ));
+                    };
 
-                    match input.peek() {
-                        Some(TokenTree::Punct(p)) => match p.as_char() {
-                            '+' => {
-                                input.next();
-
-                                if rep.is_empty() {
-                                    panic!("Empty repetition group");
-                                }
-                            }
-                            '*' => {
-                                input.next();
-                            }
-                            _ => (),
-                        },
-                        _ => (),
-                    }
+                    self.iterate(items, &id, &gr, input)
+                }
+                (None, Some(TokenTree::Group(gr)))
+                    if gr.delimiter() == Delimiter::Parenthesis && id == "product" =>
+                {
+                    let gr = gr.clone();
+                    input.next();
+                    self.product(&gr, input)
+                }
+                _ => self.variable(id, input),
+            },
+            TokenTree::Group(gr) => match gr.delimiter() {
+                Delimiter::Bracket if expr::is_arithmetic(&gr.stream()) => {
+                    let value = expr::eval(self, gr.stream(), gr.span())?;
+                    expr::int_literal(value, expr::parse_int_type(input).as_deref(), gr.span())
+                }
+                Delimiter::Bracket => Ok(TokenStream::from(TokenTree::Ident(functions::to_ident(
+                    &self.make_string(gr.stream())?,
+                    gr.span(),
+                )?))),
+                Delimiter::Parenthesis => {
+                    let Some(ref items) = self.ctx.repeatable else {
+                        return Err(syn::Error::new(
+                            gr.span(),
+                            "nothing to repeat in this context",
+                        ));
+                    };
 
-                    out
+                    self.repeat(items, &gr, input)
                 }
-                _ => panic!(),
+                _ => Err(syn::Error::new(gr.span(), "expected `$[...]` or `$(...)`")),
             },
-            _ => panic!(),
+            other => Err(syn::Error::new(
+                other.span(),
+                "expected a variable, `$[...]` or `$(...)` after `$`",
+            )),
         }
     }
 
-    fn make_ident(&self, input: TokenStream) -> Ident {
-        let mut name = String::new();
+    /// Looks up a variable. Integer values can be given a type, like
+    /// `$index:u16`.
+    fn variable(&self, first: Ident, input: &mut Peekable<IntoIter>) -> syn::Result<TokenStream> {
+        let var = self.parse_var(first, input);
 
-        for tt in self.translate(input) {
+        // `$meta_route:or("/")` is replaced with the default if the variable
+        // is not set, like for implementers without a `route` option.
+        if let Some(default) = parse_default(input) {
+            return match self.get(&var) {
+                Some(value) => Ok(value.clone()),
+                None => self.translate(default.stream()),
+            };
+        }
+
+        let value = self.lookup(&var)?;
+
+        match condition::as_int(&value) {
+            Some(int) => match expr::parse_int_type(input) {
+                Some(ty) => expr::int_literal(int, Some(&ty), var.span()),
+                None => Ok(value),
+            },
+            None => Ok(value),
+        }
+    }
+
+    /// Translates the first block of a `$if ... { } $else $if ... { } $else { }`
+    /// chain whose condition holds.
+    fn conditional(&self, input: &mut Peekable<IntoIter>) -> syn::Result<TokenStream> {
+        let mut branches = vec![parse_branch(input)?];
+
+        while let Some(keyword) = parse_keyword(input, "else") {
+            if parse_keyword(input, "if").is_some() {
+                branches.push(parse_branch(input)?);
+                continue;
+            }
+
+            match input.next() {
+                Some(TokenTree::Group(block)) if block.delimiter() == Delimiter::Brace => {
+                    branches.push((None, block));
+                    break;
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        keyword.span(),
+                        "expected `{` after `$else`",
+                    ))
+                }
+            }
+        }
+
+        for (condition, block) in branches {
+            let holds = match condition {
+                Some(condition) => condition.eval(self)?,
+                None => true,
+            };
+
+            if holds {
+                return self.translate(block.stream());
+            }
+        }
+
+        Ok(TokenStream::new())
+    }
+
+    /// Translates the group once for each of the items, followed by the optional
+    /// separator and the repetition operator. With the `?` operator, the group
+    /// is translated once in the current scope if there are any items, and the
+    /// `$else { ... }` block that may follow it otherwise.
+    fn repeat(
+        &self,
+        items: &[Context],
+        group: &Group,
+        input: &mut Peekable<IntoIter>,
+    ) -> syn::Result<TokenStream> {
+        let (separator, op) = parse_repetition_operator(input);
+
+        if op.as_ref().is_some_and(|op| op.as_char() == '?') {
+            if let Some(separator) = separator {
+                return Err(syn::Error::new(
+                    separator.span(),
+                    "the `?` operator does not take a separator",
+                ));
+            }
+
+            let fallback = match parse_keyword(input, "else") {
+                Some(keyword) => match parse_group(input, Delimiter::Brace) {
+                    Some(block) => Some(block),
+                    None => {
+                        return Err(syn::Error::new(
+                            keyword.span(),
+                            "expected `{` after `$else`",
+                        ))
+                    }
+                },
+                None => None,
+            };
+
+            return match (items.is_empty(), fallback) {
+                (false, _) => self.translate(group.stream()),
+                (true, Some(block)) => self.translate(block.stream()),
+                (true, None) => Ok(TokenStream::new()),
+            };
+        }
+
+        if items.is_empty() {
+            if let Some(op) = op.filter(|op| op.as_char() == '+') {
+                return Err(syn::Error::new(op.span(), "empty repetition group"));
+            }
+        }
+
+        let mut out = TokenStream::new();
+
+        for (i, ctx) in items.iter().enumerate() {
+            if i > 0 {
+                out.extend(separator.clone());
+            }
+
+            out.extend(self.child(ctx).translate(group.stream())?);
+        }
+
+        Ok(out)
+    }
+
+    /// Repeats the items in reverse with `$rev(...)`, as adjacent pairs with
+    /// `$pairs(...)` or in groups of N with `$chunks(N)(...)`. Each pair binds
+    /// its items to `$prev` and `$next`, while the items of a chunk are
+    /// repeated with `$(...)`. The items of a named source are repeated by
+    /// giving the mode after its name, like `$msgs:rev(...)`.
+    fn iterate(
+        &self,
+        items: &[Context],
+        mode: &Ident,
+        group: &Group,
+        input: &mut Peekable<IntoIter>,
+    ) -> syn::Result<TokenStream> {
+        match mode.to_string().as_str() {
+            // The items keep their `$index`, but are positioned in reverse
+            // order for `$rev_index`, `$is_first`, `$is_last`, `first` and `last`.
+            "rev" => self.repeat(&positioned(items.iter().rev().cloned()), group, input),
+            "pairs" => {
+                let pairs = numbered(items.windows(2).map(|pair| Context {
+                    bindings: HashMap::from_iter([
+                        ("prev".into(), pair[0].clone()),
+                        ("next".into(), pair[1].clone()),
+                    ]),
+                    ..Default::default()
+                }));
+
+                self.repeat(&pairs, group, input)
+            }
+            _ => {
+                let size = expr::eval(self, group.stream(), group.span())?;
+
+                if size <= 0 {
+                    return Err(syn::Error::new(
+                        group.span(),
+                        "the size of chunks must be positive",
+                    ));
+                }
+
+                let Some(body) = parse_group(input, Delimiter::Parenthesis) else {
+                    return Err(syn::Error::new(
+                        group.span(),
+                        "expected `(...)` after `$chunks(N)`",
+                    ));
+                };
+
+                let chunks = numbered(items.chunks(size as usize).map(|chunk| {
+                    let items = numbered(chunk.to_vec());
+
+                    Context {
+                        sources: HashMap::from_iter([("implementers".into(), items.clone())]),
+                        repeatable: Some(items),
+                        ..Default::default()
+                    }
+                }));
+
+                self.repeat(&chunks, &body, input)
+            }
+        }
+    }
+
+    /// Repeats every combination of the implementers of the given queries,
+    /// which are bound to their names. For example:
+    /// `$product(a = Dto, b = Dto)( $if a != b { impl From<$a> for $b { ... } } )*`
+    /// A query that is just a name may also refer to a repetition source.
+    fn product(&self, args: &Group, input: &mut Peekable<IntoIter>) -> syn::Result<TokenStream> {
+        let bindings = Punctuated::<Binding, Token![,]>::parse_terminated.parse2(args.stream())?;
+
+        if bindings.is_empty() {
+            return Err(syn::Error::new(
+                args.span(),
+                "expected at least one binding",
+            ));
+        }
+
+        let Some(body) = parse_group(input, Delimiter::Parenthesis) else {
+            return Err(syn::Error::new(
+                args.span(),
+                "expected `(...)` after `$product(...)`",
+            ));
+        };
+
+        let mut combinations = vec![HashMap::new()];
+
+        for Binding { name, query } in bindings {
+            let items = match query.name().and_then(|n| self.source(&n.to_string())) {
+                Some(items) => items.to_vec(),
+                None => self.query(&query, name.span())?,
+            };
+
+            let mut next = Vec::new();
+
+            for combination in combinations {
+                for item in &items {
+                    let mut combination: HashMap<String, Context> = combination.clone();
+                    combination.insert(name.to_string(), item.clone());
+                    next.push(combination);
+                }
+            }
+
+            combinations = next;
+        }
+
+        let items = numbered(combinations.into_iter().map(|bindings| Context {
+            bindings,
+            ..Default::default()
+        }));
+
+        self.repeat(&items, &body, input)
+    }
+
+    /// Returns the contexts of the implementers that are selected by a query.
+    fn query(&self, query: &Query, span: Span) -> syn::Result<Vec<Context>> {
+        let mut scope = Some(self);
+
+        while let Some(s) = scope {
+            if let Some(lookup) = &s.ctx.lookup {
+                return Ok(numbered(query.select(|name, paths| lookup(name, paths))?));
+            }
+
+            scope = s.parent;
+        }
+
+        Err(syn::Error::new(
+            span,
+            "queries are not available in this context",
+        ))
+    }
+
+    /// Takes the qualifiers and the name of a variable, like `outer.index`.
+    fn parse_var(&self, first: Ident, input: &mut Peekable<IntoIter>) -> Var {
+        let mut qualifiers = Vec::new();
+        let mut name = first;
+
+        while self.is_qualifier(&name) {
+            let mut lookahead = input.clone();
+
+            let (Some(TokenTree::Punct(p)), Some(TokenTree::Ident(next))) =
+                (lookahead.next(), lookahead.next())
+            else {
+                break;
+            };
+
+            if p.as_char() != '.' {
+                break;
+            }
+
+            // `$handler.handle(msg)` is the path of a bound type followed by a
+            // method call, unless the bound context has a variable of that name
+            // that is not called, like `$handler.kind` but not `$handler.kind()`.
+            let next_name = next.to_string();
+            let is_call = matches!(
+                lookahead.next(),
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis
+            );
+
+            if self.binding(&name).is_some_and(|ctx| {
+                is_call || ctx.var(&next_name).is_none() && !ctx.bindings.contains_key(&next_name)
+            }) {
+                break;
+            }
+
+            input.next();
+            input.next();
+
+            qualifiers.push(std::mem::replace(&mut name, next));
+        }
+
+        Var { qualifiers, name }
+    }
+
+    /// Concatenates the translated tokens of `$[...]` or `$str[...]` into a
+    /// string, calling the functions in them. For example:
+    /// `$[snake(trim_suffix($ty, Request)) _handler]`
+    fn make_string(&self, input: TokenStream) -> syn::Result<String> {
+        let mut tokens = input.into_iter().peekable();
+        let mut out = String::new();
+
+        while let Some(tt) = tokens.next() {
             match tt {
-                TokenTree::Group(_) => panic!(),
-                TokenTree::Ident(id) => {
-                    name.push_str(&id.to_string());
+                TokenTree::Punct(p) if p.as_char() == '$' => {
+                    for tt in self.translate_expression(&p, &mut tokens)? {
+                        push_token(&mut out, tt)?;
+                    }
                 }
-                TokenTree::Punct(p) => {
-                    name.push(p.as_char());
+                TokenTree::Ident(id) if functions::is_function(&id) => {
+                    let Some(args) = parse_group(&mut tokens, Delimiter::Parenthesis) else {
+                        push_token(&mut out, TokenTree::Ident(id))?;
+                        continue;
+                    };
+
+                    let args = split_args(args.stream())
+                        .into_iter()
+                        .map(|arg| self.make_string(arg))
+                        .collect::<syn::Result<Vec<_>>>()?;
+
+                    out.push_str(&functions::call(&id, &args)?);
                 }
-                TokenTree::Literal(lit) => {
-                    // TODO: Remove all forbidden characters
-                    name.push_str(&lit.to_string().trim_matches('"'));
+                other => push_token(&mut out, other)?,
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Takes the default of `$var:or(...)` from the input, if it is next.
+fn parse_default(input: &mut Peekable<IntoIter>) -> Option<Group> {
+    let mut lookahead = input.clone();
+
+    match (lookahead.next(), lookahead.next(), lookahead.next()) {
+        (Some(TokenTree::Punct(p)), Some(TokenTree::Ident(or)), Some(TokenTree::Group(g)))
+            if p.as_char() == ':'
+                && p.spacing() == Spacing::Alone
+                && or == "or"
+                && g.delimiter() == Delimiter::Parenthesis =>
+        {
+            input.next();
+            input.next();
+            input.next();
+            Some(g)
+        }
+        _ => None,
+    }
+}
+
+/// Takes the iteration mode that follows the name of a source, like `:rev(...)`
+/// in `$msgs:rev(...)`, from the input.
+fn parse_mode(input: &mut Peekable<IntoIter>) -> Option<(Ident, Group)> {
+    let mut lookahead = input.clone();
+
+    match (lookahead.next(), lookahead.next(), lookahead.next()) {
+        (Some(TokenTree::Punct(p)), Some(TokenTree::Ident(mode)), Some(TokenTree::Group(g)))
+            if p.as_char() == ':'
+                && p.spacing() == Spacing::Alone
+                && matches!(mode.to_string().as_str(), "rev" | "pairs" | "chunks")
+                && g.delimiter() == Delimiter::Parenthesis =>
+        {
+            input.next();
+            input.next();
+            input.next();
+            Some((mode, g))
+        }
+        _ => None,
+    }
+}
+
+/// Takes a group with the given delimiter from the input, if it is next.
+fn parse_group(input: &mut Peekable<IntoIter>, delimiter: Delimiter) -> Option<Group> {
+    match input.peek() {
+        Some(TokenTree::Group(g)) if g.delimiter() == delimiter => {
+            let g = g.clone();
+            input.next();
+            Some(g)
+        }
+        _ => None,
+    }
+}
+
+/// Appends the text of a token to a name that is built with `$[...]` or
+/// `$str[...]`. String
+/// literals are added without their quotes.
+fn push_token(name: &mut String, tt: TokenTree) -> syn::Result<()> {
+    match tt {
+        TokenTree::Group(g) => {
+            return Err(syn::Error::new(
+                g.span(),
+                "groups are not allowed in `$[...]` or `$str[...]`",
+            ))
+        }
+        TokenTree::Ident(id) => {
+            let id = id.to_string();
+            name.push_str(id.strip_prefix("r#").unwrap_or(&id));
+        }
+        TokenTree::Punct(p) => {
+            name.push(p.as_char());
+        }
+        TokenTree::Literal(lit) => match syn::parse2::<syn::LitStr>(lit.to_token_stream()) {
+            Ok(s) => name.push_str(&s.value()),
+            Err(_) => name.push_str(&lit.to_string()),
+        },
+    }
+
+    Ok(())
+}
+
+/// Splits the arguments of a function call at the commas between them.
+fn split_args(input: TokenStream) -> Vec<TokenStream> {
+    let mut args = vec![TokenStream::new()];
+
+    for tt in input {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == ',' => args.push(TokenStream::new()),
+            other => args.last_mut().unwrap().extend(once(other)),
+        }
+    }
+
+    args
+}
+
+/// Takes the condition and the block of a `$if`, which extends up to the first
+/// `{ ... }` group.
+fn parse_branch(input: &mut Peekable<IntoIter>) -> syn::Result<(Option<Condition>, Group)> {
+    let mut condition = TokenStream::new();
+
+    loop {
+        match input.next() {
+            Some(TokenTree::Group(block)) if block.delimiter() == Delimiter::Brace => {
+                if condition.is_empty() {
+                    return Err(syn::Error::new(block.span(), "expected a condition"));
                 }
+
+                return Ok((Some(syn::parse2(condition)?), block));
+            }
+            Some(tt) => condition.extend(once(tt)),
+            None => {
+                return Err(syn::Error::new(
+                    Span::call_site(),
+                    "expected `{` after the condition of `$if`",
+                ))
             }
         }
+    }
+}
+
+/// Takes `$` followed by the given keyword from the input, if they are next.
+fn parse_keyword(input: &mut Peekable<IntoIter>, keyword: &str) -> Option<Ident> {
+    let mut lookahead = input.clone();
 
-        Ident::new(&name, Span::call_site())
+    match (lookahead.next(), lookahead.next()) {
+        (Some(TokenTree::Punct(p)), Some(TokenTree::Ident(id)))
+            if p.as_char() == '$' && id == keyword =>
+        {
+            input.next();
+            input.next();
+            Some(id)
+        }
+        _ => None,
     }
 }
 
+/// Takes the optional separator and the repetition operator (`*`, `+` or `?`) that
+/// follow a repetition group. Like in `macro_rules!`, the separator may be any
+/// single token, for example: `$( $ty ),*`
+fn parse_repetition_operator(input: &mut Peekable<IntoIter>) -> (Option<TokenTree>, Option<Punct>) {
+    fn is_operator(tt: Option<&TokenTree>) -> bool {
+        matches!(tt, Some(TokenTree::Punct(p)) if matches!(p.as_char(), '*' | '+' | '?'))
+    }
+
+    let mut lookahead = input.clone();
+
+    let separator = match lookahead.next() {
+        Some(TokenTree::Group(_)) | None => return (None, None),
+        tt if is_operator(tt.as_ref()) => None,
+        // The separator is always followed by the operator, so it would be
+        // joined with it.
+        Some(TokenTree::Punct(p)) if is_operator(lookahead.peek()) => {
+            input.next();
+
+            let mut separator = Punct::new(p.as_char(), Spacing::Alone);
+            separator.set_span(p.span());
+
+            Some(TokenTree::Punct(separator))
+        }
+        Some(tt) if is_operator(lookahead.peek()) => {
+            input.next();
+            Some(tt)
+        }
+        _ => return (None, None),
+    };
+
+    let Some(TokenTree::Punct(op)) = input.next() else {
+        unreachable!();
+    };
+
+    (separator, Some(op))
+}
+
 #[test]
 fn test_simple_translation() {
     let input: TokenStream = "pub struct Foobar ( usize, String )".parse().unwrap();
 
-    // dbg!(Context::default().translate(input));
-
-    // todo!()
+    assert_eq!(
+        Context::default()
+            .translate(input.clone())
+            .unwrap()
+            .to_string(),
+        input.to_string(),
+    );
 }
 
 #[test]
@@ -229,13 +1402,25 @@ fn test_simple_vars() {
             TokenStream::from(TokenTree::Ident(Ident::new("Foobar", Span::call_site()))),
         )]),
         repeatable: None,
+        ..Default::default()
     };
 
     let input: TokenStream = "pub struct $foo;".parse().unwrap();
 
-    // dbg!(ctx.translate(input));
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "pub struct Foobar ;",
+    );
+}
 
-    // todo!()
+#[test]
+fn test_unknown_var() {
+    let input: TokenStream = "pub struct $foo;".parse().unwrap();
+
+    assert_eq!(
+        Context::default().translate(input).unwrap_err().to_string(),
+        "unknown variable `$foo`",
+    );
 }
 
 #[test]
@@ -257,15 +1442,17 @@ fn test_identifiers() {
             ),
         ]),
         repeatable: None,
+        ..Default::default()
     };
 
     let input: TokenStream = "enum Bar { $[ $name _Req \"123\" ]($path :: $name) }"
         .parse()
         .unwrap();
 
-    // dbg!(ctx.translate(input).to_string());
-
-    // todo!()
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "enum Bar { DoLogin_Req123 (foo :: bar :: DoLogin) }",
+    );
 }
 
 #[test]
@@ -279,6 +1466,7 @@ fn test_repetition() {
                     TokenStream::from(TokenTree::Ident(Ident::new("DoLogin", Span::call_site()))),
                 )]),
                 repeatable: None,
+                ..Default::default()
             },
             Context {
                 vars: HashMap::from_iter([(
@@ -286,17 +1474,1076 @@ fn test_repetition() {
                     TokenStream::from(TokenTree::Ident(Ident::new("DoLogout", Span::call_site()))),
                 )]),
                 repeatable: None,
+                ..Default::default()
             },
         ]),
+        ..Default::default()
     };
 
-    dbg!("foo::bar::Goo".parse::<TokenStream>());
-
     let input: TokenStream = "enum Bar { $($[ $name _Req \"123\" ](usize),)+ }"
         .parse()
         .unwrap();
 
-    // dbg!(ctx.translate(input).to_string());
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "enum Bar { DoLogin_Req123 (usize) , DoLogout_Req123 (usize) , }",
+    );
+}
+
+#[test]
+fn test_assoc_vars() {
+    let ctx = Context::from_iter([Implementer {
+        name: "GetContacts".into(),
+        path: "crate::contacts".into(),
+        assoc: vec![(
+            "Response".into(),
+            "Vec < crate :: contacts :: Contact >".into(),
+        )],
+        ..Default::default()
+    }]);
+
+    let input: TokenStream = "$( $ty => $assoc_Response, )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "GetContacts => Vec < crate :: contacts :: Contact > ,",
+    );
+}
+
+#[test]
+fn test_query() {
+    let implementers = |opcodes: &[(&str, &str)]| -> Vec<Context> {
+        opcodes
+            .iter()
+            .map(|(name, opcode)| {
+                Context::from(Implementer {
+                    name: name.to_string(),
+                    path: "crate".into(),
+                    consts: vec![("OPCODE".into(), opcode.to_string())],
+                    ..Default::default()
+                })
+            })
+            .collect()
+    };
+
+    let query: Query = syn::parse_str(
+        "Message where $const_OPCODE > 0 && $const_OPCODE != 300 order by $const_OPCODE desc",
+    )
+    .unwrap();
+
+    let ctx: Context = query
+        .apply(implementers(&[
+            ("Ping", "0"),
+            ("GetSelf", "11"),
+            ("GetUsers", "2"),
+            ("Logout", "300"),
+        ]))
+        .unwrap()
+        .into_iter()
+        .collect();
+
+    let input: TokenStream = "$( $index => $ty = $const_OPCODE, )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "0 => GetSelf = 11 , 1 => GetUsers = 2 ,",
+    );
+
+    // Expressions are not evaluated, so they can be neither filtered nor
+    // sorted by, and are not compared by their text either.
+    let opcodes = [("Ping", "2"), ("GetSelf", "crate :: BASE + 1")];
+
+    for (query, error) in [
+        (
+            "Message order by $const_OPCODE",
+            "`$const_OPCODE` of `crate::GetSelf` is not an integer: `crate :: BASE + 1`",
+        ),
+        (
+            "Message where $const_OPCODE < 10",
+            "`$const_OPCODE` of `crate::GetSelf` is not an integer: `crate :: BASE + 1`",
+        ),
+        (
+            "Message where $const_OPCODE < \"10\"",
+            "`\"10\"` is not an integer",
+        ),
+    ] {
+        let query: Query = syn::parse_str(query).unwrap();
+
+        assert_eq!(
+            query
+                .apply(implementers(&opcodes))
+                .err()
+                .unwrap()
+                .to_string(),
+            error,
+        );
+    }
+}
+
+#[test]
+fn test_impl_qualifiers() {
+    let implementers = [("Ping", false), ("Raw", true)].map(|(name, is_unsafe)| Implementer {
+        name: name.into(),
+        path: "crate".into(),
+        is_unsafe,
+        ..Default::default()
+    });
+
+    let query: Query = syn::parse_str("Wire where !$is_unsafe").unwrap();
+
+    let ctx: Context = query
+        .apply(implementers.into_iter().map(Context::from).collect())
+        .unwrap()
+        .into_iter()
+        .collect();
+
+    let input: TokenStream = "$( $ty: $is_unsafe $is_default, )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "Ping : false false ,",
+    );
+}
+
+#[test]
+fn test_derive_statements() {
+    let input: GenerateInput = syn::parse_str(
+        "derive MessageDerive => Message; derive proto::Model => Entity + Persist; (Message) => {}",
+    )
+    .unwrap();
+
+    assert_eq!(input.derives.traits_of("MessageDerive"), ["Message"]);
+    assert_eq!(
+        input.derives.traits_of("crate::proto::Model"),
+        ["Entity", "Persist"]
+    );
+    assert_eq!(input.derives.traits_of("Model"), ["Model"]);
+}
+
+#[test]
+fn test_cfg() {
+    let ctx = Context::from_iter([
+        Implementer {
+            name: "Settings".into(),
+            path: "crate".into(),
+            cfg: Some("feature = \"serde\"".into()),
+            ..Default::default()
+        },
+        Implementer {
+            name: "Profile".into(),
+            path: "crate".into(),
+            ..Default::default()
+        },
+    ]);
+
+    let input: TokenStream = "$( $cfg $ty, )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "# [cfg (feature = \"serde\")] Settings , Profile ,",
+    );
+}
+
+#[test]
+fn test_separators() {
+    let ctx = Context::from_iter(["crate::Login", "crate::users::Logout"].map(Implementer::from));
+
+    let input: TokenStream = "fn f($( $ty: $ty_full ),*) where $( $ty: Send ),* {}"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "fn f (Login : crate :: Login , Logout : crate :: users :: Logout) \
+         where Login : Send , Logout : Send { }",
+    );
+
+    let input: TokenStream = "($( $ty ),*) $( $index );*".parse().unwrap();
+
+    assert_eq!(
+        Context::from_iter([Implementer::from("crate::Login")])
+            .translate(input)
+            .unwrap()
+            .to_string(),
+        "(Login) 0",
+    );
+}
+
+#[test]
+fn test_scoped_vars() {
+    let ctx = Context::from_iter(["crate::Login", "crate::Logout"].map(Implementer::from));
+
+    let input: TokenStream = "$( $ty = $index / $count, )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "Login = 0 / 2 , Logout = 1 / 2 ,",
+    );
+
+    let nested = Context {
+        vars: HashMap::from_iter([("index".into(), "7".parse().unwrap())]),
+        repeatable: Some(vec![ctx]),
+        ..Default::default()
+    };
+
+    let input: TokenStream = "$( $( $index $outer.count $outer.index $outer.outer.index, )* )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        nested.translate(input).unwrap().to_string(),
+        "0 2 7 7 , 1 2 7 7 ,",
+    );
+
+    let input: TokenStream = "$( $outer.ty )*".parse().unwrap();
+
+    assert_eq!(
+        Context::from_iter([Implementer::from("crate::Login")])
+            .translate(input)
+            .unwrap_err()
+            .to_string(),
+        "unknown variable `$outer.ty`",
+    );
+}
+
+#[test]
+fn test_named_sources() {
+    let ctx = Context::from_iter(
+        [
+            "crate::users::GetSelf",
+            "crate::Ping",
+            "crate::users::SearchUsers",
+        ]
+        .map(Implementer::from),
+    );
+
+    let input: TokenStream =
+        "$modules( $mod: $mod_path [ $implementers( $ty $index / $count $outer.index ),* ]; )*"
+            .parse()
+            .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "users : crate :: users [GetSelf 0 / 2 0 , SearchUsers 1 / 2 0] ; \
+         crate : crate [Ping 0 / 1 1] ;",
+    );
+
+    let input: TokenStream = "$implementers( $ty $index )* $( $ty )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "GetSelf 0 Ping 1 SearchUsers 2 GetSelf Ping SearchUsers",
+    );
+}
+
+#[test]
+fn test_fields() {
+    let ctx = Context::from_iter([
+        Implementer {
+            shape: Some(Shape::Named),
+            fields: vec![
+                Field {
+                    name: Some("limit".into()),
+                    ty: "usize".into(),
+                    attrs: vec![],
+                },
+                Field {
+                    name: Some("after".into()),
+                    ty: "Option < crate :: User >".into(),
+                    attrs: vec!["# [serde (default)]".into()],
+                },
+            ],
+            ..Implementer::from("crate::GetUsers")
+        },
+        Implementer {
+            shape: Some(Shape::Tuple),
+            fields: vec![Field {
+                name: None,
+                ty: "u32".into(),
+                attrs: vec![],
+            }],
+            ..Implementer::from("crate::GetUser")
+        },
+        Implementer {
+            shape: Some(Shape::Unit),
+            ..Implementer::from("crate::Ping")
+        },
+    ]);
+
+    let input: TokenStream =
+        "$( $ty $shape { $fields( $field_attrs $field_index $field_name: $field_ty ),* } )*"
+            .parse()
+            .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "GetUsers named { 0 limit : usize , # [serde (default)] 1 after : Option < crate :: User > } \
+         GetUser tuple { 0 0 : u32 } \
+         Ping unit { }",
+    );
+}
+
+#[test]
+fn test_variants() {
+    let ctx = Context::from_iter([
+        Implementer {
+            kind: Some(Kind::Enum),
+            variants: vec![
+                Variant {
+                    name: "Create".into(),
+                    attrs: vec![],
+                    shape: Shape::Unit,
+                    fields: vec![],
+                    discriminant: Some("1".into()),
+                },
+                Variant {
+                    name: "Rename".into(),
+                    attrs: vec![],
+                    shape: Shape::Named,
+                    fields: vec![Field {
+                        name: Some("to".into()),
+                        ty: "String".into(),
+                        attrs: vec![],
+                    }],
+                    discriminant: None,
+                },
+            ],
+            ..Implementer::from("crate::UserPermission")
+        },
+        Implementer {
+            kind: Some(Kind::Struct),
+            shape: Some(Shape::Unit),
+            ..Implementer::from("crate::User")
+        },
+    ]);
+
+    let input: TokenStream = "$( $kind $ty [ $variants( \
+            $variant_index $ty::$variant $variant_shape = $variant_discriminant \
+            { $fields( $field_name: $field_ty ),* } \
+        ),* ] )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "enum UserPermission [\
+            0 UserPermission ::Create unit = 1 { } , \
+            1 UserPermission ::Rename named = { to : String }\
+         ] \
+         struct User []",
+    );
+}
+
+#[test]
+fn test_conditionals() {
+    let ctx = Context::from_iter([
+        Implementer {
+            kind: Some(Kind::Enum),
+            attrs: vec!["# [derive (Clone)]".into()],
+            ..Implementer::from("crate::Status")
+        },
+        Implementer {
+            kind: Some(Kind::Struct),
+            shape: Some(Shape::Named),
+            fields: vec![
+                Field {
+                    name: Some("id".into()),
+                    ty: "u32".into(),
+                    attrs: vec![],
+                },
+                Field {
+                    name: Some("cache".into()),
+                    ty: "Vec < u8 >".into(),
+                    attrs: vec!["# [serde (skip)]".into()],
+                },
+            ],
+            ..Implementer::from("crate::User")
+        },
+        Implementer {
+            kind: Some(Kind::Struct),
+            shape: Some(Shape::Unit),
+            ..Implementer::from("crate::Ping")
+        },
+    ]);
+
+    // `$shape` is not set for enums and `$kind` is not set for implementers
+    // whose definition was not found, so comparing them is false.
+    let mixed = Context::from_iter([
+        Implementer {
+            kind: Some(Kind::Enum),
+            ..Implementer::from("crate::Status")
+        },
+        Implementer::from("crate::Unknown"),
+        Implementer {
+            kind: Some(Kind::Struct),
+            shape: Some(Shape::Unit),
+            ..Implementer::from("crate::Ping")
+        },
+    ]);
+
+    let input: TokenStream = "$( \
+            $if $shape == unit { unit } $else $if $kind == struct { struct } \
+            $else $if $kind != struct { other } $else { unknown } $ty; \
+        )*"
+    .parse()
+    .unwrap();
+
+    assert_eq!(
+        mixed.translate(input).unwrap().to_string(),
+        "other Status ; unknown Unknown ; unit Ping ;",
+    );
+
+    let input: TokenStream = "$( \
+            $if first { begin } \
+            $if $kind == enum { enum $ty } \
+            $else $if $shape == unit { unit $ty } \
+            $else { $ty { $fields( $if attr(serde) { skipped } $field_name ),* } } \
+            $if attr(derive) && !$missing { derived } \
+            $if last { end } $else { , } \
+        )*"
+    .parse()
+    .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "begin enum Status derived , User { id , skipped cache } , unit Ping end",
+    );
+
+    let input: TokenStream = "$( $ty )* $else { }".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "`$else` without a preceding `$if`",
+    );
+
+    let input: TokenStream = "$if { }".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "expected a condition",
+    );
+}
+
+#[test]
+fn test_ident_functions() {
+    let ctx = Context::from_iter(
+        [
+            "crate::GetUserRequest",
+            "crate::HTTPServer_v2",
+            "crate::Type",
+        ]
+        .map(Implementer::from),
+    );
+
+    let input: TokenStream = "$( \
+            $[snake($ty)] $[shouty($ty)] $[camel($ty)] $[pascal(snake($ty))] $[kebab($ty)] \
+            $[trim_suffix($ty, Request)] $[trim_prefix($ty, \"Get\")] $[replace($ty, User, Self)] \
+            $[$index snake($ty)]; \
+        )*"
+    .parse()
+    .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "get_user_request GET_USER_REQUEST getUserRequest GetUserRequest get_user_request \
+         GetUser UserRequest GetSelfRequest _0get_user_request ; \
+         http_server_v2 HTTP_SERVER_V2 httpServerV2 HttpServerV2 http_server_v2 \
+         HTTPServer_v2 HTTPServer_v2 HTTPServer_v2 _1http_server_v2 ; \
+         r#type TYPE r#type Type r#type \
+         Type Type Type _2type ;",
+    );
+
+    let input: TokenStream = "$( $[snake($ty, Request)] )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "`snake` takes 1 argument, but 2 were given",
+    );
+
+    let input: TokenStream = "$[trim_suffix(Request, Request)]".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "the identifier is empty",
+    );
+}
+
+#[test]
+fn test_strings() {
+    let ctx = Context::from_iter(["crate::users::GetSelf", "crate::Ping"].map(Implementer::from));
+
+    let input: TokenStream =
+        "$( ($ty_str, $path_str, $str[kebab($ty) - $index], $str[$mod_path]) )*"
+            .parse()
+            .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "(\"GetSelf\" , \"crate::users::GetSelf\" , \"get-self-0\" , \"crate::users\") \
+         (\"Ping\" , \"crate::Ping\" , \"ping-1\" , \"crate\")",
+    );
+}
+
+#[test]
+fn test_escapes() {
+    let ctx = Context::from_iter(["crate::GetSelf", "crate::Ping"].map(Implementer::from));
+
+    let input: TokenStream = "macro_rules! requests { \
+            ($$callback:ident) => { $$callback!($( $ty ),*) }; \
+        } \
+        $raw { $( $ty )* $[x] }"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "macro_rules ! requests { \
+            ($ callback : ident) => { $ callback ! (GetSelf , Ping) } ; \
+         } \
+         $ ($ ty) * $ [x]",
+    );
+}
+
+#[test]
+fn test_arithmetic() {
+    let ctx = Context::from_iter(["crate::GetSelf", "crate::Ping"].map(Implementer::from));
+
+    let input: TokenStream = "$( \
+            [$[index + 1]:u16, $index:u16, $[count - $index - 1], $[(index + 4) * 10 % 7], $[-index]:i8] \
+            $ty: u32 \
+        )* $count:usize"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "[1u16 , 0u16 , 1 , 5 , 0i8] GetSelf : u32 \
+         [2u16 , 1u16 , 0 , 1 , - 1i8] Ping : u32 \
+         2usize",
+    );
+
+    for (input, error) in [
+        ("$( $[index / 0] )*", "division by zero"),
+        ("$( $[index + 300]:u8 )*", "`300` is out of range for `u8`"),
+        ("$( $[ty + 1] )*", "`GetSelf` is not an integer"),
+    ] {
+        assert_eq!(
+            ctx.translate(input.parse().unwrap())
+                .unwrap_err()
+                .to_string(),
+            error,
+        );
+    }
+}
+
+#[test]
+fn test_optional() {
+    let ctx = Context::from_iter(["crate::GetSelf", "crate::Ping"].map(Implementer::from));
+
+    let input: TokenStream = "$( enum Request { $( $ty ),* } )? $else { enum Request {} } \
+        $( $ty $index $rev_index $is_first $is_last; )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input.clone()).unwrap().to_string(),
+        "enum Request { GetSelf , Ping } \
+         GetSelf 0 1 true false ; Ping 1 0 false true ;",
+    );
+
+    assert_eq!(
+        Context::from_iter(Vec::<Implementer>::new())
+            .translate(input)
+            .unwrap()
+            .to_string(),
+        "enum Request { }",
+    );
+
+    let input: TokenStream = "$( $ty ),?".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "the `?` operator does not take a separator",
+    );
+}
+
+#[test]
+fn test_iteration_modes() {
+    let ctx = Context::from_iter(["crate::A", "crate::B", "crate::C"].map(Implementer::from));
+
+    let input: TokenStream = "$rev( $ty $index ),* ; \
+        $pairs( impl Next for $prev.ty { type Next = $next.ty; } )* \
+        $chunks(count - 1)( ( $( $ty $index ),* ) )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "C 2 , B 1 , A 0 ; \
+         impl Next for A { type Next = B ; } \
+         impl Next for B { type Next = C ; } \
+         (A 0 , B 1) (C 0)",
+    );
+
+    let input: TokenStream =
+        "$rev( $if first { first } $if last { last } $ty $index $rev_index $is_first ),*"
+            .parse()
+            .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "first C 2 2 true , B 1 1 false , last A 0 0 false",
+    );
+
+    let input: TokenStream = "$chunks(0)( $ty )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "the size of chunks must be positive",
+    );
+
+    let mut named = Context::default();
+    named.sources.insert(
+        "msgs".into(),
+        numbered(
+            ["crate::A", "crate::B", "crate::C"].map(|path| Context::from(Implementer::from(path))),
+        ),
+    );
+
+    let input: TokenStream = "$msgs:rev( $ty $index $if first { first } ),* ; \
+        $msgs:pairs( $prev.ty < $next.ty ),* ; \
+        $msgs:chunks(2)( ( $( $ty ),* ) )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        named.translate(input).unwrap().to_string(),
+        "C 2 first , B 1 , A 0 ; A < B , B < C ; (A , B) (C)",
+    );
+
+    let input: TokenStream = "$pairs( $prev.ty )*".parse().unwrap();
+
+    assert_eq!(
+        named.translate(input).unwrap_err().to_string(),
+        "`$pairs` only repeats the implementers of an unnamed query, \
+         use `$msgs:pairs(...)` for named sources",
+    );
+
+    let ctx = Context::from_iter([Implementer {
+        fields: ["id", "name", "email"]
+            .map(|name| Field {
+                name: Some(name.into()),
+                ty: "String".into(),
+                attrs: Vec::new(),
+            })
+            .to_vec(),
+        ..Implementer::from("crate::User")
+    }]);
+
+    let input: TokenStream = "$( $fields:rev( $field_name ),* ; $implementers:rev( $ty ) )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "email , name , id ; User",
+    );
+}
+
+#[test]
+fn test_named_queries() {
+    let input: GenerateInput = syn::parse_str(
+        "derive Model => Event; \
+         msgs = Message where $const_OPCODE < 10 order by $const_OPCODE, events = Event, => {}",
+    )
+    .unwrap();
+
+    assert_eq!(
+        input
+            .queries
+            .iter()
+            .map(|(name, query)| (
+                name.as_ref().unwrap().to_string(),
+                query.lookup(|t, _| vec![Implementer::from(t)])[0]
+                    .name
+                    .clone()
+            ))
+            .collect::<Vec<_>>(),
+        [
+            ("msgs".into(), "Message".into()),
+            ("events".into(), "Event".into())
+        ],
+    );
+
+    let input: GenerateInput = syn::parse_str("(Message) => {}").unwrap();
+
+    assert!(input.queries[0].0.is_none());
+
+    let Err(error) = syn::parse_str::<GenerateInput>("a = Message, a = Event => {}") else {
+        panic!("expected an error");
+    };
+
+    assert_eq!(error.to_string(), "`a` is bound more than once");
+}
+
+#[test]
+fn test_trait_sets() {
+    let lookup = |name: &str, _: &PathFilter| -> Vec<Implementer> {
+        let paths: &[&str] = match name {
+            "Message" => &["crate::GetSelf", "crate::Ping", "crate::admin::Reset"],
+            "Serialize" => &["crate::Ping", "crate::GetSelf", "crate::User"],
+            "Internal" => &["crate::GetSelf"],
+            "Event" => &["crate::User", "crate::Login"],
+            _ => &[],
+        };
+
+        paths.iter().copied().map(Implementer::from).collect()
+    };
+
+    for (query, expected) in [
+        ("Message + Serialize", &["GetSelf", "Ping"][..]),
+        ("Message + Serialize - Internal", &["Ping"]),
+        (
+            "Message - Internal | Event",
+            &["Ping", "Reset", "User", "Login"],
+        ),
+        ("Message + (Internal | Event)", &["GetSelf"]),
+        (
+            "Serialize | Event where $ty != Login",
+            &["Ping", "GetSelf", "User"],
+        ),
+    ] {
+        let query: Query = syn::parse_str(query).unwrap();
+
+        let names: Vec<_> = query
+            .apply(
+                query
+                    .lookup(lookup)
+                    .into_iter()
+                    .map(Context::from)
+                    .collect(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|ctx| ctx.var("ty").unwrap().to_string())
+            .collect();
+
+        assert_eq!(names, expected);
+    }
+}
+
+#[test]
+fn test_product() {
+    let mut ctx = Context::from_iter(["crate::Login", "crate::Logout"].map(Implementer::from));
+
+    ctx.lookup = Some(Rc::new(|name: &str, _: &PathFilter| match name {
+        "Dto" => ["crate::UserDto", "crate::api::GroupDto"]
+            .map(Implementer::from)
+            .into(),
+        _ => Vec::new(),
+    }));
+
+    let input: TokenStream = "$product(a = Dto, b = Dto)( \
+            $if a != b { impl From<$a> for $b {} } \
+        )* \
+        $product(dto = Dto where $ty != UserDto, msg = implementers)( ($dto.ty, $msg.ty, $index) ),*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "impl From <crate :: UserDto > for crate :: api :: GroupDto { } \
+         impl From <crate :: api :: GroupDto > for crate :: UserDto { } \
+         (GroupDto , Login , 0) , (GroupDto , Logout , 1)",
+    );
+
+    let input: TokenStream = "$product(a = Dto)( $a )*".parse().unwrap();
+
+    assert_eq!(
+        Context::default().translate(input).unwrap_err().to_string(),
+        "queries are not available in this context",
+    );
+}
+
+#[test]
+fn test_join() {
+    fn select(query: &str, handlers: &[(&str, &str)]) -> syn::Result<Context> {
+        let query: Query = syn::parse_str(query).unwrap();
+
+        let items = query.select(|name, _| match name {
+            "Message" => vec![
+                Implementer::from("crate::users::Get"),
+                Implementer {
+                    rename: Some("GetContact".into()),
+                    ..Implementer::from("crate::contacts::Get")
+                },
+            ],
+            "Handler" => handlers
+                .iter()
+                .map(|(path, arg)| Implementer {
+                    trait_args: vec![arg.to_string()],
+                    kind: Some(Kind::Struct),
+                    ..Implementer::from(*path)
+                })
+                .collect(),
+            _ => Vec::new(),
+        })?;
+
+        Ok(Context::from_iter(items))
+    }
+
+    // Both messages are named `Get`, and the second one is renamed, so they
+    // are joined by their full paths.
+    let handlers = [
+        ("crate::users::UserService", "crate :: users :: Get"),
+        (
+            "crate::contacts::ContactService",
+            "crate :: contacts :: Get",
+        ),
+        (
+            "crate::contacts::ContactService",
+            "crate :: contacts :: Add",
+        ),
+    ];
+
+    let query = "Message join handler = Handler<$ty_full>";
+    let ctx = select(query, &handlers).unwrap();
+    let input: TokenStream = "$( $ty => $handler.handle(msg), $handler.ty; )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "Get => crate :: users :: UserService . handle (msg) , UserService ; \
+         GetContact => crate :: contacts :: ContactService . handle (msg) , ContactService ;",
+    );
+
+    // Methods may be named like the variables of the bound context.
+    let input: TokenStream = "$( $handler.kind() $handler.kind; )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "crate :: users :: UserService . kind () struct ; \
+         crate :: contacts :: ContactService . kind () struct ;",
+    );
+
+    assert_eq!(
+        select(query, &handlers[..1]).err().unwrap().to_string(),
+        "no implementer of `Handler<crate::contacts::Get>` found",
+    );
+
+    assert_eq!(
+        select(
+            query,
+            &[
+                ("crate::users::UserService", "crate :: users :: Get"),
+                ("crate::admin::AdminService", "crate :: users :: Get"),
+                (
+                    "crate::contacts::ContactService",
+                    "crate :: contacts :: Get"
+                ),
+            ],
+        )
+        .err()
+        .unwrap()
+        .to_string(),
+        "`Handler<crate::users::Get>` is implemented by more than one type: \
+         `crate::users::UserService`, `crate::admin::AdminService`",
+    );
+}
+
+#[test]
+fn test_paths() {
+    let input: TokenStream = "$( $path_str ),*".parse().unwrap();
+
+    let translate = |query: &str| {
+        let query: Query = syn::parse_str(query).unwrap();
+
+        let items = query
+            .select(|_, paths| {
+                [
+                    "crate::Ping",
+                    "crate::api::GetSelf",
+                    "crate::api::admin::Ban",
+                ]
+                .into_iter()
+                .filter(|path| paths.matches(path))
+                .map(Implementer::from)
+                .collect()
+            })
+            .unwrap();
+
+        Context::from_iter(items)
+            .translate(input.clone())
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(
+        translate("Message in crate::api::*"),
+        "\"crate::api::GetSelf\""
+    );
+    assert_eq!(
+        translate("Message in crate::api::**"),
+        "\"crate::api::GetSelf\" , \"crate::api::admin::Ban\"",
+    );
+    assert_eq!(
+        translate("Message not in api::** where $ty != GetSelf"),
+        "\"crate::Ping\"",
+    );
+    assert_eq!(
+        translate("Message in crate::* in crate::**::Ban not in crate::Ping"),
+        "\"crate::api::admin::Ban\"",
+    );
+}
+
+#[test]
+fn test_options() {
+    let query: Query = syn::parse_str("Command where !tag(\"internal\")").unwrap();
+
+    let items = query
+        .select(|_, _| {
+            vec![
+                Implementer {
+                    rename: Some("Kick".into()),
+                    tags: vec!["admin".into(), "moderation".into()],
+                    ..Implementer::from("crate::Ban")
+                },
+                Implementer {
+                    tags: vec!["internal".into()],
+                    ..Implementer::from("crate::Reload")
+                },
+                Implementer::from("crate::Login"),
+            ]
+        })
+        .unwrap();
+
+    let input: TokenStream = "$( $ty $ty_str $ty_full [$tags] $if tag(\"admin\") { admin } ; )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        Context::from_iter(items)
+            .translate(input)
+            .unwrap()
+            .to_string(),
+        "Kick \"Kick\" crate :: Ban [\"admin\" , \"moderation\"] admin ; \
+         Login \"Login\" crate :: Login [] ;",
+    );
+}
+
+#[test]
+fn test_meta() {
+    let ctx = Context::from_iter([
+        Implementer {
+            meta: vec![
+                ("route".into(), "\"/users/self\"".into()),
+                ("handler".into(), "crate :: users :: get_self".into()),
+            ],
+            ..Implementer::from("crate::users::GetSelf")
+        },
+        Implementer {
+            meta: vec![("route".into(), "\"/ping\"".into())],
+            ..Implementer::from("crate::Ping")
+        },
+    ]);
+
+    let input: TokenStream = "$( route($meta_route, $meta_handler:or(default_handler)); )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap().to_string(),
+        "route (\"/users/self\" , crate :: users :: get_self) ; \
+         route (\"/ping\" , default_handler) ;",
+    );
+
+    let input: TokenStream = "$( $meta_handler )*".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "`handler` is missing in the `#[traitable(...)]` attribute of `crate::Ping`, \
+         use `$meta_handler:or(...)` to give a default",
+    );
+}
+
+#[test]
+fn test_nested_meta() {
+    let input: GenerateInput = syn::parse_str(
+        "msgs = Message where $ty == A, events = Event => { \
+         $msgs( $events( $meta_route:or(\"none\") $outer.meta_route ),* ) \
+         }",
+    )
+    .unwrap();
+
+    let mut ctx = Context::default();
+
+    for (name, query) in input.queries {
+        let items = query
+            .select(|name, _| match name {
+                "Message" => vec![
+                    Implementer {
+                        meta: vec![("route".into(), "\"/a\"".into())],
+                        ..Implementer::from("crate::A")
+                    },
+                    Implementer::from("crate::B"),
+                ],
+                _ => vec![Implementer::from("crate::E")],
+            })
+            .unwrap();
+
+        ctx.sources
+            .insert(name.unwrap().to_string(), numbered(items));
+    }
+
+    assert_eq!(
+        ctx.translate(input.body).unwrap().to_string(),
+        "\"none\" \"/a\"",
+    );
+
+    let input: TokenStream = "$msgs( $events( $meta_route ),* )".parse().unwrap();
+
+    assert_eq!(
+        ctx.translate(input).unwrap_err().to_string(),
+        "`route` is missing in the `#[traitable(...)]` attribute of `crate::E`, \
+         use `$meta_route:or(...)` to give a default",
+    );
+}
+
+#[test]
+fn test_explicit_indices() {
+    let select = |indices: &[Option<usize>]| {
+        let query: Query = syn::parse_str("Message").unwrap();
+
+        query.select(|_, _| {
+            ["crate::A", "crate::B", "crate::C", "crate::D"]
+                .into_iter()
+                .zip(indices)
+                .map(|(path, &index)| Implementer {
+                    index,
+                    ..Implementer::from(path)
+                })
+                .collect()
+        })
+    };
+
+    let input: TokenStream = "$( $ty = $index $if first { first } $if last { last }, )*"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        Context::from_iter(select(&[None, Some(0), None, Some(2)]).unwrap())
+            .translate(input)
+            .unwrap()
+            .to_string(),
+        "A = 1 first , B = 0 , C = 3 , D = 2 last ,",
+    );
 
-    // todo!()
+    assert_eq!(
+        select(&[Some(1), None, Some(1), None])
+            .err()
+            .unwrap()
+            .to_string(),
+        "index 1 is given to both `crate::A` and `crate::C`",
+    );
 }
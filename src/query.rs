@@ -0,0 +1,487 @@
+use std::{cmp::Ordering, collections::HashMap, iter::once};
+
+use proc_macro2::{Ident, Span, TokenStream, TokenTree};
+use syn::{
+    ext::IdentExt,
+    parenthesized,
+    parse::{Parse, ParseStream},
+    Token,
+};
+use traitable_core::{Implementer, PathFilter, PathPattern};
+
+use crate::{
+    condition::{compare, not_an_integer, Condition},
+    split_args, Context, Scope,
+};
+
+mod kw {
+    syn::custom_keyword!(order);
+    syn::custom_keyword!(by);
+    syn::custom_keyword!(asc);
+    syn::custom_keyword!(desc);
+    syn::custom_keyword!(join);
+    syn::custom_keyword!(not);
+}
+
+/// Selects the implementers that a template is expanded for. For example:
+/// `(Message in crate::api::** where $const_OPCODE < 100 order by $const_OPCODE desc)`
+pub struct Query {
+    traits: Traits,
+    paths: PathFilter,
+    joins: Vec<Join>,
+    filter: Option<Condition>,
+    order: Option<Order>,
+}
+
+/// Combines the implementers of several traits, where `+` selects the types
+/// that implement both traits, `-` the ones that implement the first but not
+/// the second trait and `|` the ones that implement either of them. `|` binds
+/// weaker than `+` and `-`. For example: `Message + Serialize - Internal`
+enum Traits {
+    Trait(Ident),
+    Intersection(Box<Traits>, Box<Traits>),
+    Difference(Box<Traits>, Box<Traits>),
+    Union(Box<Traits>, Box<Traits>),
+}
+
+/// A query bound to a name, like `msgs = Message` in `generate!` or `a = Dto`
+/// in `$product(...)`.
+pub struct Binding {
+    pub name: Ident,
+    pub query: Query,
+}
+
+/// Binds the implementer of a generic trait for each of the implementers, like
+/// `join handler = Handler<$ty_full>`, which makes the implementer of `Handler<M>`
+/// for every message `M` available as `$handler`.
+struct Join {
+    name: Ident,
+    trait_name: Ident,
+    args: TokenStream,
+}
+
+struct Order {
+    var: Ident,
+    descending: bool,
+}
+
+impl Parse for Query {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let traits = input.parse()?;
+
+        let mut paths = PathFilter::default();
+
+        loop {
+            if input.peek(Token![in]) {
+                input.parse::<Token![in]>()?;
+                paths.include.push(parse_path_pattern(input)?);
+            } else if input.peek(kw::not) && input.peek2(Token![in]) {
+                input.parse::<kw::not>()?;
+                input.parse::<Token![in]>()?;
+                paths.exclude.push(parse_path_pattern(input)?);
+            } else {
+                break;
+            }
+        }
+
+        let mut joins = Vec::new();
+
+        while input.peek(kw::join) {
+            joins.push(input.parse()?);
+        }
+
+        let filter = if input.peek(Token![where]) {
+            input.parse::<Token![where]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
+        let order = if input.peek(kw::order) {
+            input.parse::<kw::order>()?;
+            input.parse::<kw::by>()?;
+            input.parse::<Token![$]>()?;
+
+            let var = input.call(Ident::parse_any)?;
+
+            let descending = if input.peek(kw::desc) {
+                input.parse::<kw::desc>()?;
+                true
+            } else {
+                if input.peek(kw::asc) {
+                    input.parse::<kw::asc>()?;
+                }
+                false
+            };
+
+            Some(Order { var, descending })
+        } else {
+            None
+        };
+
+        Ok(Query {
+            traits,
+            paths,
+            joins,
+            filter,
+            order,
+        })
+    }
+}
+
+impl Parse for Binding {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+
+        Ok(Binding {
+            name,
+            query: input.parse()?,
+        })
+    }
+}
+
+/// Makes sure that no index is given with `#[traitable(index = N)]` to more than
+/// one of the implementers.
+fn check_indices(items: &[Context]) -> syn::Result<()> {
+    let mut taken = HashMap::new();
+
+    for ctx in items {
+        let Some(index) = ctx.index else {
+            continue;
+        };
+
+        let path = ctx
+            .var("path_str")
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        let path = path.trim_matches('"').to_string();
+
+        if let Some(other) = taken.insert(index, path.clone()) {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                format!("index {index} is given to both `{other}` and `{path}`"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a pattern for the paths of implementers, like `crate::api::*` or
+/// `crate::api::**`.
+fn parse_path_pattern(input: ParseStream) -> syn::Result<PathPattern> {
+    let mut pattern = String::new();
+
+    loop {
+        if input.peek(Token![*]) {
+            input.parse::<Token![*]>()?;
+            pattern.push('*');
+
+            if input.peek(Token![*]) {
+                input.parse::<Token![*]>()?;
+                pattern.push('*');
+            }
+        } else {
+            pattern.push_str(&input.call(Ident::parse_any)?.to_string());
+        }
+
+        if !input.peek(Token![::]) {
+            return Ok(PathPattern::new(&pattern));
+        }
+
+        input.parse::<Token![::]>()?;
+        pattern.push_str("::");
+    }
+}
+
+impl Parse for Join {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<kw::join>()?;
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let trait_name = input.parse()?;
+        input.parse::<Token![<]>()?;
+
+        // The arguments are a template, so they are taken as tokens up to the
+        // matching `>`.
+        let mut args = TokenStream::new();
+        let mut depth = 0;
+
+        loop {
+            let tt: TokenTree = input.parse()?;
+
+            if let TokenTree::Punct(p) = &tt {
+                match p.as_char() {
+                    '<' => depth += 1,
+                    '>' if depth == 0 => break,
+                    '>' => depth -= 1,
+                    _ => (),
+                }
+            }
+
+            args.extend(once(tt));
+        }
+
+        Ok(Join {
+            name,
+            trait_name,
+            args,
+        })
+    }
+}
+
+impl Parse for Traits {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut lhs = Self::parse_sum(input)?;
+
+        while input.peek(Token![|]) && !input.peek(Token![||]) {
+            input.parse::<Token![|]>()?;
+            lhs = Traits::Union(Box::new(lhs), Box::new(Self::parse_sum(input)?));
+        }
+
+        Ok(lhs)
+    }
+}
+
+impl Traits {
+    fn parse_sum(input: ParseStream) -> syn::Result<Self> {
+        let mut lhs = Self::parse_trait(input)?;
+
+        loop {
+            if input.peek(Token![+]) {
+                input.parse::<Token![+]>()?;
+                lhs = Traits::Intersection(Box::new(lhs), Box::new(Self::parse_trait(input)?));
+            } else if input.peek(Token![-]) {
+                input.parse::<Token![-]>()?;
+                lhs = Traits::Difference(Box::new(lhs), Box::new(Self::parse_trait(input)?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn parse_trait(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(syn::token::Paren) {
+            let inner;
+            parenthesized!(inner in input);
+            return inner.parse();
+        }
+
+        Ok(Traits::Trait(input.parse()?))
+    }
+
+    /// Returns the implementers of the combined traits. Implementers are the
+    /// same if they have the same path, and keep the associated items of the
+    /// first trait they were found for.
+    fn lookup(&self, lookup: &impl Fn(&str) -> Vec<Implementer>) -> Vec<Implementer> {
+        fn contains(implementers: &[Implementer], imp: &Implementer) -> bool {
+            implementers
+                .iter()
+                .any(|i| i.path == imp.path && i.name == imp.name)
+        }
+
+        match self {
+            Traits::Trait(name) => lookup(&name.to_string()),
+            Traits::Intersection(a, b) => {
+                let b = b.lookup(lookup);
+                a.lookup(lookup)
+                    .into_iter()
+                    .filter(|imp| contains(&b, imp))
+                    .collect()
+            }
+            Traits::Difference(a, b) => {
+                let b = b.lookup(lookup);
+                a.lookup(lookup)
+                    .into_iter()
+                    .filter(|imp| !contains(&b, imp))
+                    .collect()
+            }
+            Traits::Union(a, b) => {
+                let mut a = a.lookup(lookup);
+
+                for imp in b.lookup(lookup) {
+                    if !contains(&a, &imp) {
+                        a.push(imp);
+                    }
+                }
+
+                a
+            }
+        }
+    }
+}
+
+impl Query {
+    /// Returns the name of the trait if the query is just a single name, which
+    /// may also refer to a repetition source.
+    pub fn name(&self) -> Option<&Ident> {
+        match (&self.traits, &self.joins[..], &self.filter, &self.order) {
+            (Traits::Trait(name), [], None, None) if self.paths == PathFilter::default() => {
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    /// Looks up the implementers of the traits of the query, with the given
+    /// function that returns the implementers of a single trait in the modules
+    /// that match a filter.
+    pub fn lookup(
+        &self,
+        lookup: impl Fn(&str, &PathFilter) -> Vec<Implementer>,
+    ) -> Vec<Implementer> {
+        self.traits.lookup(&|name: &str| lookup(name, &self.paths))
+    }
+
+    /// Returns the contexts of the implementers that are selected by the query,
+    /// with the implementers of the joined traits bound to them.
+    pub fn select(
+        &self,
+        lookup: impl Fn(&str, &PathFilter) -> Vec<Implementer>,
+    ) -> syn::Result<Vec<Context>> {
+        let implementers = self.lookup(&lookup);
+        let mut items = self.apply(implementers.into_iter().map(Context::from).collect())?;
+
+        check_indices(&items)?;
+
+        for join in &self.joins {
+            let candidates = lookup(&join.trait_name.to_string(), &PathFilter::default());
+
+            for ctx in &mut items {
+                let imp = join.find(&candidates, ctx)?;
+                ctx.bindings
+                    .insert(join.name.to_string(), Context::from(imp.clone()));
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Filters and sorts the contexts of the implementers that were found for
+    /// the trait.
+    pub fn apply(&self, items: Vec<Context>) -> syn::Result<Vec<Context>> {
+        let mut items = match &self.filter {
+            Some(filter) => {
+                let mut result = Vec::new();
+
+                for ctx in items {
+                    if filter.eval(&Scope::new(&ctx))? {
+                        result.push(ctx);
+                    }
+                }
+
+                result
+            }
+            None => items,
+        };
+
+        if let Some(order) = &self.order {
+            let name = order.var.to_string();
+
+            let mut keyed = Vec::new();
+
+            for ctx in items {
+                let Some(key) = ctx.var(&name).cloned() else {
+                    return Err(syn::Error::new(
+                        order.var.span(),
+                        format!("unknown variable `${name}`"),
+                    ));
+                };
+
+                keyed.push((key, ctx));
+            }
+
+            // Every key is compared with the first one, so that keys which
+            // cannot be compared are reported instead of sorted arbitrarily.
+            if let Some((first, first_ctx)) = keyed.first() {
+                for (key, ctx) in &keyed {
+                    if let Err(value) = compare(first, key) {
+                        let ctx = if std::ptr::eq(value, key) {
+                            ctx
+                        } else {
+                            first_ctx
+                        };
+                        let path = ctx
+                            .var("path_str")
+                            .and_then(|path| syn::parse2::<syn::LitStr>(path.clone()).ok());
+
+                        return Err(not_an_integer(
+                            order.var.span(),
+                            &format!("${name}"),
+                            path.map(|path| path.value()),
+                            value,
+                        ));
+                    }
+                }
+            }
+
+            keyed.sort_by(|(a, _), (b, _)| {
+                let ord = compare(a, b).unwrap_or(Ordering::Equal);
+
+                match order.descending {
+                    true => ord.reverse(),
+                    false => ord,
+                }
+            });
+
+            items = keyed.into_iter().map(|(_, ctx)| ctx).collect();
+        }
+
+        Ok(items)
+    }
+}
+
+impl Join {
+    /// Finds the implementer of the trait whose arguments are the translated
+    /// arguments of the join. The type arguments of impls are resolved from the
+    /// crate root, so types have to be given by their full path, like
+    /// `$ty_full`.
+    fn find<'a>(
+        &self,
+        candidates: &'a [Implementer],
+        ctx: &Context,
+    ) -> syn::Result<&'a Implementer> {
+        fn normalize(s: &str) -> String {
+            s.chars().filter(|c| !c.is_whitespace()).collect()
+        }
+
+        let args: Vec<String> = split_args(Scope::new(ctx).translate(self.args.clone())?)
+            .iter()
+            .map(|arg| normalize(&arg.to_string()))
+            .collect();
+
+        let found: Vec<&Implementer> = candidates
+            .iter()
+            .filter(|imp| {
+                imp.trait_args.len() == args.len()
+                    && imp
+                        .trait_args
+                        .iter()
+                        .zip(&args)
+                        .all(|(have, want)| normalize(have) == *want)
+            })
+            .collect();
+
+        let name = format!("{}<{}>", self.trait_name, args.join(", "));
+
+        match found[..] {
+            [imp] => Ok(imp),
+            [] => Err(syn::Error::new(
+                self.trait_name.span(),
+                format!("no implementer of `{name}` found"),
+            )),
+            _ => Err(syn::Error::new(
+                self.trait_name.span(),
+                format!(
+                    "`{name}` is implemented by more than one type: {}",
+                    found
+                        .iter()
+                        .map(|imp| format!("`{}::{}`", imp.path, imp.name))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            )),
+        }
+    }
+}